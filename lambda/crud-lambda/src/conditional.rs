@@ -0,0 +1,248 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use axum::http::{
+    header::{ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED},
+    HeaderMap, HeaderName, HeaderValue,
+};
+use serde_json::Value;
+
+use crate::{
+    error::ApiError,
+    store::{Condition, DocumentPath, Item},
+};
+
+/// Attribute holding the item's version, bumped on every write and exposed as
+/// its `ETag`.
+pub const VERSION: &str = "_version";
+
+/// Attribute holding the time of the item's last write in milliseconds since
+/// the Unix epoch, exposed as its `Last-Modified`.
+pub const UPDATED_AT: &str = "_updated_at";
+
+pub fn now() -> Value {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    Value::from(millis as u64)
+}
+
+pub fn version(item: &Item) -> Option<u64> {
+    item.get(VERSION)?.as_u64()
+}
+
+fn updated_at(item: &Item) -> Option<SystemTime> {
+    let millis = item.get(UPDATED_AT)?.as_u64()?;
+    Some(UNIX_EPOCH + Duration::from_millis(millis))
+}
+
+/// The `ETag` and `Last-Modified` headers describing `item`.
+pub fn validators(item: &Item) -> Vec<(HeaderName, HeaderValue)> {
+    let etag = version(item)
+        .and_then(|version| HeaderValue::from_str(&format!("\"{}\"", version)).ok())
+        .map(|value| (ETAG, value));
+    let last_modified = updated_at(item)
+        .and_then(|time| HeaderValue::from_str(&httpdate::fmt_http_date(time)).ok())
+        .map(|value| (LAST_MODIFIED, value));
+
+    etag.into_iter().chain(last_modified).collect()
+}
+
+/// Whether a conditional GET can be answered with 304. `If-None-Match` takes
+/// precedence over `If-Modified-Since` (RFC 9110, section 13.2.2).
+pub fn not_modified(headers: &HeaderMap, item: &Item) -> bool {
+    if let Some(value) = headers.get(IF_NONE_MATCH) {
+        let Ok(value) = value.to_str() else {
+            return false;
+        };
+        let current = version(item).map(|version| format!("\"{}\"", version));
+        return value
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || Some(tag.trim_start_matches("W/")) == current.as_deref());
+    }
+
+    let since = headers
+        .get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok());
+    match (since, updated_at(item)) {
+        // HTTP dates have whole-second precision.
+        (Some(since), Some(updated_at)) => {
+            let secs = |time: SystemTime| {
+                time.duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+            };
+            secs(updated_at) <= secs(since)
+        }
+        _ => false,
+    }
+}
+
+/// A parsed `If-Match` header.
+#[derive(Debug, Clone, Copy)]
+pub enum IfMatch {
+    Any,
+    Version(u64),
+}
+
+impl IfMatch {
+    pub fn condition(self, pk: &str) -> Condition {
+        match self {
+            IfMatch::Any => Condition::Exists(DocumentPath::attr(pk)),
+            IfMatch::Version(v) => Condition::Equals(DocumentPath::attr(VERSION), Value::from(v)),
+        }
+    }
+
+    pub fn matches(self, item: &Item) -> bool {
+        match self {
+            IfMatch::Any => true,
+            IfMatch::Version(v) => version(item) == Some(v),
+        }
+    }
+}
+
+pub fn parse_if_match(headers: &HeaderMap) -> Result<Option<IfMatch>, ApiError> {
+    let Some(value) = headers.get(IF_MATCH) else {
+        return Ok(None);
+    };
+    let invalid = || ApiError::BadRequest("unsupported If-Match value".into());
+
+    let value = value.to_str().map_err(|_| invalid())?.trim();
+    if value == "*" {
+        return Ok(Some(IfMatch::Any));
+    }
+
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .and_then(|version| version.parse().ok())
+        .map(|version| Some(IfMatch::Version(version)))
+        .ok_or_else(invalid)
+}
+
+/// Turns an `If-Match` header into the condition the write must meet: `*`
+/// requires the item to exist, an entity tag requires its version to match.
+pub fn if_match(headers: &HeaderMap, pk: &str) -> Result<Option<Condition>, ApiError> {
+    Ok(parse_if_match(headers)?.map(|if_match| if_match.condition(pk)))
+}
+
+/// Condition that the item is still as it was when read: absent for `None`,
+/// otherwise at the given version, where `Some(None)` stands for an item
+/// written before versioning.
+pub fn unchanged(pk: &str, version: Option<Option<u64>>) -> Condition {
+    match version {
+        None => Condition::NotExists(DocumentPath::attr(pk)),
+        Some(Some(version)) => Condition::Equals(DocumentPath::attr(VERSION), Value::from(version)),
+        Some(None) => Condition::And(vec![
+            Condition::Exists(DocumentPath::attr(pk)),
+            Condition::NotExists(DocumentPath::attr(VERSION)),
+        ]),
+    }
+}
+
+/// Whether the client sent `If-None-Match: *`, i.e. asked to only create.
+pub fn if_none_match_any(headers: &HeaderMap) -> bool {
+    headers
+        .get(IF_NONE_MATCH)
+        .is_some_and(|value| value.as_bytes() == b"*")
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn headers(name: HeaderName, value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(name, HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test]
+    fn parses_if_match() {
+        assert!(matches!(parse_if_match(&HeaderMap::new()), Ok(None)));
+        assert!(matches!(
+            parse_if_match(&headers(IF_MATCH, "*")),
+            Ok(Some(IfMatch::Any))
+        ));
+        assert!(matches!(
+            parse_if_match(&headers(IF_MATCH, " \"7\" ")),
+            Ok(Some(IfMatch::Version(7)))
+        ));
+        for value in ["7", "\"seven\"", "W/\"7\"", "\"1\", \"2\""] {
+            assert!(
+                matches!(
+                    parse_if_match(&headers(IF_MATCH, value)),
+                    Err(ApiError::BadRequest(_))
+                ),
+                "{}",
+                value
+            );
+        }
+    }
+
+    #[test]
+    fn if_match_matches_the_version() {
+        let item: Item = serde_json::from_value(json!({"id": "a", VERSION: 3})).unwrap();
+        assert!(IfMatch::Any.matches(&item));
+        assert!(IfMatch::Version(3).matches(&item));
+        assert!(!IfMatch::Version(2).matches(&item));
+        assert!(IfMatch::Version(3).condition("id").matches(Some(&item)));
+        assert!(!IfMatch::Any.condition("id").matches(None));
+    }
+
+    #[test]
+    fn unchanged_covers_missing_and_unversioned_items() {
+        let unversioned: Item = serde_json::from_value(json!({"id": "a"})).unwrap();
+        let versioned: Item = serde_json::from_value(json!({"id": "a", VERSION: 1})).unwrap();
+
+        assert!(unchanged("id", None).matches(None));
+        assert!(!unchanged("id", None).matches(Some(&unversioned)));
+        assert!(unchanged("id", Some(None)).matches(Some(&unversioned)));
+        assert!(!unchanged("id", Some(None)).matches(Some(&versioned)));
+        assert!(unchanged("id", Some(Some(1))).matches(Some(&versioned)));
+        assert!(!unchanged("id", Some(Some(2))).matches(Some(&versioned)));
+    }
+
+    #[test]
+    fn not_modified_compares_entity_tags() {
+        let item: Item = serde_json::from_value(json!({"id": "a", VERSION: 3})).unwrap();
+        let matches = |value| not_modified(&headers(IF_NONE_MATCH, value), &item);
+
+        assert!(matches("\"3\""));
+        assert!(matches("W/\"3\""));
+        assert!(matches("\"1\", \"3\""));
+        assert!(matches("*"));
+        assert!(!matches("\"2\""));
+        assert!(!not_modified(&HeaderMap::new(), &item));
+    }
+
+    #[test]
+    fn not_modified_compares_whole_seconds() {
+        // 1000.5s after the epoch, which HTTP dates round down to 1000s.
+        let item: Item =
+            serde_json::from_value(json!({"id": "a", VERSION: 1, UPDATED_AT: 1_000_500})).unwrap();
+        let since = |secs| {
+            let date = httpdate::fmt_http_date(UNIX_EPOCH + Duration::from_secs(secs));
+            not_modified(&headers(IF_MODIFIED_SINCE, &date), &item)
+        };
+
+        assert!(since(1000));
+        assert!(since(2000));
+        assert!(!since(999));
+
+        // If-None-Match wins over If-Modified-Since.
+        let mut both = headers(IF_NONE_MATCH, "\"2\"");
+        both.insert(
+            IF_MODIFIED_SINCE,
+            HeaderValue::from_str(&httpdate::fmt_http_date(
+                UNIX_EPOCH + Duration::from_secs(2000),
+            ))
+            .unwrap(),
+        );
+        assert!(!not_modified(&both, &item));
+    }
+}
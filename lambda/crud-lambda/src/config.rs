@@ -0,0 +1,306 @@
+use std::{env, net::SocketAddr, num::NonZeroUsize, str::FromStr, time::Duration};
+
+use aws_config::{retry::RetryMode, BehaviorVersion, SdkConfig};
+use lambda_http::Error;
+
+use crate::store::{Index, KeySchema};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    Dynamo,
+    Memory,
+}
+
+/// Settings read from the environment once at startup.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub table_name: String,
+    pub pk: String,
+    /// Sort key of the table, if it has one. Item routes then take both keys
+    /// (`/items/:id/:sk`), and `/items/:id` queries the items of a partition.
+    pub sk: Option<String>,
+    /// Secondary indexes `GET /items?index=` may query, from `INDEXES` as a
+    /// comma-separated list of `name=pk` or `name=pk:sk`.
+    pub indexes: Vec<Index>,
+    pub backend: Backend,
+    /// Also serve item routes at the pre-`/items` paths (`/:id`). Not
+    /// available on tables with a sort key.
+    pub legacy_routes: bool,
+    /// Table for `Idempotency-Key` records. Without one, the DynamoDB backend
+    /// ignores the header; the in-memory backend always keeps records.
+    pub idempotency_table: Option<String>,
+    /// How long a response is replayed for repeats of its idempotency key.
+    pub idempotency_ttl: Duration,
+    /// How long an idempotency key stays claimed by a request that has not
+    /// finished, after which a retry may take it over. Must outlast the
+    /// function timeout.
+    pub idempotency_lock: Duration,
+    /// Most segments of a parallel scan read at the same time.
+    pub scan_concurrency: NonZeroUsize,
+    /// Longest a parallel scan reads for before handing back a cursor.
+    pub scan_time_budget: Duration,
+    /// Time kept back from the Lambda deadline for sending the response of a
+    /// parallel scan.
+    pub scan_time_margin: Duration,
+    /// Serve HTTP on this address instead of running as a Lambda function,
+    /// for local development.
+    pub local_addr: Option<SocketAddr>,
+    /// Stream responses from Lambda rather than buffering them, which the
+    /// function URL or API must be set up for. Only exports are served then.
+    pub response_streaming: bool,
+    pub dynamodb: DynamoConfig,
+}
+
+/// Overrides for the DynamoDB client; anything left unset falls back to the
+/// SDK defaults (including its own `AWS_*` environment variables).
+#[derive(Debug, Clone, Default)]
+pub struct DynamoConfig {
+    pub endpoint_url: Option<String>,
+    pub retry_mode: Option<RetryMode>,
+    pub max_attempts: Option<u32>,
+    pub connect_timeout: Option<Duration>,
+    pub operation_timeout: Option<Duration>,
+}
+
+fn required(name: &str) -> Result<String, Error> {
+    env::var(name).map_err(|_| format!("{} must be set", name).into())
+}
+
+fn optional<T>(name: &str) -> Result<Option<T>, Error>
+where
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    env::var(name)
+        .ok()
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|e| format!("invalid {}: {}", name, e).into())
+        })
+        .transpose()
+}
+
+fn indexes(name: &str) -> Result<Vec<Index>, Error> {
+    let Ok(value) = env::var(name) else {
+        return Ok(vec![]);
+    };
+
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|index| !index.is_empty())
+        .map(|index| {
+            let invalid = || format!("invalid {} entry {:?}", name, index).into();
+            let (index_name, keys) = index.split_once('=').ok_or_else(invalid)?;
+            let (pk, sk) = match keys.split_once(':') {
+                Some((pk, sk)) => (pk, Some(sk)),
+                None => (keys, None),
+            };
+            if index_name.is_empty() || pk.is_empty() || sk == Some("") {
+                return Err(invalid());
+            }
+            Ok(Index {
+                name: index_name.to_string(),
+                keys: KeySchema {
+                    pk: pk.to_string(),
+                    sk: sk.map(String::from),
+                },
+            })
+        })
+        .collect()
+}
+
+fn millis(name: &str) -> Result<Option<Duration>, Error> {
+    Ok(optional(name)?.map(Duration::from_millis))
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, Error> {
+        let backend = match env::var("STORE").as_deref() {
+            Ok("memory") => Backend::Memory,
+            Ok("dynamodb") | Err(_) => Backend::Dynamo,
+            Ok(other) => return Err(format!("unknown STORE {:?}", other).into()),
+        };
+
+        Ok(Self {
+            table_name: required("TABLE_NAME")?,
+            pk: required("PK")?,
+            sk: env::var("SK").ok(),
+            indexes: indexes("INDEXES")?,
+            backend,
+            legacy_routes: optional("LEGACY_ROUTES")?.unwrap_or(false),
+            idempotency_table: env::var("IDEMPOTENCY_TABLE_NAME").ok(),
+            idempotency_ttl: Duration::from_secs(
+                optional("IDEMPOTENCY_TTL_SECS")?.unwrap_or(24 * 60 * 60),
+            ),
+            idempotency_lock: Duration::from_secs(optional("IDEMPOTENCY_LOCK_SECS")?.unwrap_or(60)),
+            scan_concurrency: optional("SCAN_CONCURRENCY")?
+                .unwrap_or(NonZeroUsize::new(4).unwrap()),
+            scan_time_budget: millis("SCAN_TIME_BUDGET_MS")?.unwrap_or(Duration::from_secs(25)),
+            scan_time_margin: millis("SCAN_TIME_MARGIN_MS")?.unwrap_or(Duration::from_secs(1)),
+            local_addr: optional("LOCAL_ADDR")?,
+            response_streaming: optional("RESPONSE_STREAMING")?.unwrap_or(false),
+            dynamodb: DynamoConfig {
+                endpoint_url: env::var("DYNAMODB_ENDPOINT").ok(),
+                retry_mode: optional("DYNAMODB_RETRY_MODE")?,
+                max_attempts: optional("DYNAMODB_MAX_ATTEMPTS")?,
+                connect_timeout: millis("DYNAMODB_CONNECT_TIMEOUT_MS")?,
+                operation_timeout: millis("DYNAMODB_OPERATION_TIMEOUT_MS")?,
+            },
+        })
+    }
+
+    pub fn keys(&self) -> KeySchema {
+        KeySchema {
+            pk: self.pk.clone(),
+            sk: self.sk.clone(),
+        }
+    }
+
+    pub fn index(&self, name: &str) -> Option<&Index> {
+        self.indexes.iter().find(|index| index.name == name)
+    }
+}
+
+impl DynamoConfig {
+    pub async fn load(&self) -> SdkConfig {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+
+        if let Some(endpoint_url) = &self.endpoint_url {
+            loader = loader.endpoint_url(endpoint_url);
+        }
+
+        if self.retry_mode.is_some() || self.max_attempts.is_some() {
+            let mut retry = aws_config::retry::RetryConfig::standard();
+            if let Some(mode) = self.retry_mode {
+                retry = retry.with_retry_mode(mode);
+            }
+            if let Some(max_attempts) = self.max_attempts {
+                retry = retry.with_max_attempts(max_attempts);
+            }
+            loader = loader.retry_config(retry);
+        }
+
+        if self.connect_timeout.is_some() || self.operation_timeout.is_some() {
+            let mut timeout = aws_config::timeout::TimeoutConfig::builder();
+            if let Some(connect_timeout) = self.connect_timeout {
+                timeout = timeout.connect_timeout(connect_timeout);
+            }
+            if let Some(operation_timeout) = self.operation_timeout {
+                timeout = timeout.operation_timeout(operation_timeout);
+            }
+            loader = loader.timeout_config(timeout.build());
+        }
+
+        loader.load().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_indexes() {
+        env::set_var("TEST_INDEXES", "byStatus=status:due, byOwner=owner,");
+        let parsed = indexes("TEST_INDEXES").unwrap();
+        let parsed: Vec<_> = parsed
+            .iter()
+            .map(|index| {
+                (
+                    index.name.as_str(),
+                    index.keys.pk.as_str(),
+                    index.keys.sk.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                ("byStatus", "status", Some("due")),
+                ("byOwner", "owner", None)
+            ]
+        );
+
+        for invalid in ["byStatus", "=status", "byStatus=", "byStatus=status:"] {
+            env::set_var("TEST_INVALID_INDEXES", invalid);
+            assert!(indexes("TEST_INVALID_INDEXES").is_err(), "{}", invalid);
+        }
+        assert!(indexes("TEST_UNSET_INDEXES").unwrap().is_empty());
+    }
+
+    // The only test reading the variables `Config::from_env` does, which are
+    // shared by the whole process.
+    #[test]
+    fn reads_settings_from_env() {
+        env::set_var("TABLE_NAME", "items");
+        env::set_var("PK", "itemId");
+        env::set_var("STORE", "memory");
+        env::set_var("DYNAMODB_RETRY_MODE", "adaptive");
+        env::set_var("DYNAMODB_MAX_ATTEMPTS", "5");
+        env::set_var("DYNAMODB_CONNECT_TIMEOUT_MS", "250");
+        env::set_var("DYNAMODB_OPERATION_TIMEOUT_MS", "2000");
+        env::set_var("SCAN_TIME_BUDGET_MS", "10000");
+
+        let config = Config::from_env().unwrap();
+        assert_eq!(config.table_name, "items");
+        assert_eq!(config.backend, Backend::Memory);
+        assert_eq!(config.dynamodb.retry_mode, Some(RetryMode::Adaptive));
+        assert_eq!(config.dynamodb.max_attempts, Some(5));
+        assert_eq!(
+            config.dynamodb.connect_timeout,
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(
+            config.dynamodb.operation_timeout,
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(config.scan_time_budget, Duration::from_secs(10));
+        assert_eq!(config.scan_time_margin, Duration::from_secs(1));
+
+        env::remove_var("STORE");
+        assert_eq!(Config::from_env().unwrap().backend, Backend::Dynamo);
+
+        for (name, invalid) in [
+            ("STORE", "postgres"),
+            ("DYNAMODB_RETRY_MODE", "eager"),
+            ("DYNAMODB_MAX_ATTEMPTS", "-1"),
+            ("DYNAMODB_CONNECT_TIMEOUT_MS", "1s"),
+            ("SCAN_CONCURRENCY", "0"),
+        ] {
+            let valid = env::var(name).ok();
+            env::set_var(name, invalid);
+            assert!(Config::from_env().is_err(), "{}={}", name, invalid);
+            match valid {
+                Some(valid) => env::set_var(name, valid),
+                None => env::remove_var(name),
+            }
+        }
+
+        env::remove_var("PK");
+        assert!(Config::from_env().is_err());
+    }
+
+    #[tokio::test]
+    async fn loads_client_overrides() {
+        env::set_var("AWS_REGION", "eu-west-1");
+        let sdk_config = DynamoConfig {
+            endpoint_url: Some("http://localhost:8000".into()),
+            retry_mode: Some(RetryMode::Adaptive),
+            max_attempts: Some(5),
+            connect_timeout: Some(Duration::from_millis(250)),
+            operation_timeout: None,
+        }
+        .load()
+        .await;
+
+        assert_eq!(sdk_config.endpoint_url(), Some("http://localhost:8000"));
+        let retry = sdk_config.retry_config().unwrap();
+        assert_eq!(retry.mode(), RetryMode::Adaptive);
+        assert_eq!(retry.max_attempts(), 5);
+        let timeout = sdk_config.timeout_config().unwrap();
+        assert_eq!(timeout.connect_timeout(), Some(Duration::from_millis(250)));
+        assert_eq!(timeout.operation_timeout(), None);
+    }
+}
@@ -0,0 +1,165 @@
+use axum::{
+    extract::rejection::{JsonRejection, PathRejection, QueryRejection},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
+};
+use serde_json::{json, Value};
+
+use crate::store::StoreError;
+
+/// Errors returned by the handlers, rendered as RFC 7807 problem documents.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("item not found")]
+    NotFound,
+    #[error("{0}")]
+    Conflict(String),
+    #[error("item has been modified")]
+    PreconditionFailed,
+    #[error("{0}")]
+    Validation(String),
+    #[error("request rate too high, retry later")]
+    Throttled,
+    #[error("{1}")]
+    Rejected(StatusCode, String),
+    /// A transaction was canceled; holds why each operation failed, if it did.
+    #[error("transaction canceled")]
+    Canceled(Vec<Option<ApiError>>),
+    #[error("internal server error")]
+    Internal(#[source] StoreError),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            ApiError::NotFound => StatusCode::NOT_FOUND,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            ApiError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ApiError::Validation(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Throttled => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Rejected(status, _) => *status,
+            ApiError::Canceled(_) => StatusCode::CONFLICT,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::ConditionFailed => ApiError::Conflict("condition check failed".into()),
+            StoreError::Conflict(detail) => ApiError::Conflict(detail),
+            StoreError::Validation(detail) => ApiError::BadRequest(detail),
+            StoreError::Throttled => ApiError::Throttled,
+            StoreError::Canceled(reasons) => ApiError::Canceled(
+                reasons
+                    .into_iter()
+                    .map(|reason| reason.map(ApiError::from))
+                    .collect(),
+            ),
+            e => ApiError::Internal(e),
+        }
+    }
+}
+
+impl From<JsonRejection> for ApiError {
+    fn from(rejection: JsonRejection) -> Self {
+        match rejection {
+            JsonRejection::JsonDataError(e) => ApiError::Validation(e.body_text()),
+            rejection => ApiError::Rejected(rejection.status(), rejection.body_text()),
+        }
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::Rejected(rejection.status(), rejection.body_text())
+    }
+}
+
+impl From<PathRejection> for ApiError {
+    fn from(rejection: PathRejection) -> Self {
+        ApiError::Rejected(rejection.status(), rejection.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if let ApiError::Internal(e) = &self {
+            tracing::error!("error: {:?}", e);
+        }
+
+        let status = self.status();
+        let mut body = json!({
+            "type": "about:blank",
+            "title": status.canonical_reason(),
+            "status": status.as_u16(),
+            "detail": self.to_string(),
+        });
+        if let ApiError::Canceled(reasons) = &self {
+            body["operations"] = reasons
+                .iter()
+                .map(|reason| match reason {
+                    Some(reason) => json!({
+                        "status": reason.status().as_u16(),
+                        "detail": reason.to_string(),
+                    }),
+                    None => Value::Null,
+                })
+                .collect();
+        }
+
+        let mut response = (
+            status,
+            [(header::CONTENT_TYPE, "application/problem+json")],
+            body.to_string(),
+        )
+            .into_response();
+        if let ApiError::Throttled = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, header::HeaderValue::from_static("1"));
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_store_errors_to_statuses() {
+        let cases = [
+            (StoreError::ConditionFailed, StatusCode::CONFLICT),
+            (
+                StoreError::Validation("bad".into()),
+                StatusCode::BAD_REQUEST,
+            ),
+            (StoreError::Throttled, StatusCode::TOO_MANY_REQUESTS),
+            (
+                StoreError::Backend("down".into()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ),
+        ];
+        for (e, status) in cases {
+            assert_eq!(ApiError::from(e).status(), status);
+        }
+    }
+
+    #[test]
+    fn throttling_asks_to_retry() {
+        let response = ApiError::Throttled.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "1");
+    }
+
+    #[test]
+    fn internal_errors_do_not_leak_details() {
+        let e = ApiError::from(StoreError::Backend("secret table name".into()));
+        assert_eq!(e.to_string(), "internal server error");
+    }
+}
@@ -0,0 +1,130 @@
+use axum::{
+    body::Body,
+    http::{header::ACCEPT, HeaderMap},
+};
+use futures_util::stream;
+use tokio::sync::mpsc;
+
+use crate::{
+    scan::{ScanProgress, SegmentPage},
+    store::{Item, StoreError},
+};
+
+/// Media type of newline-delimited JSON, one item per line.
+pub const NDJSON: &str = "application/x-ndjson";
+
+/// The media ranges of the `Accept` headers, without their parameters.
+fn media_ranges(headers: &HeaderMap) -> impl Iterator<Item = &str> {
+    headers
+        .get_all(ACCEPT)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|range| range.split(';').next().unwrap_or_default().trim())
+}
+
+/// Whether the client takes NDJSON, which it does if it did not say.
+pub fn accepts_ndjson(headers: &HeaderMap) -> bool {
+    let mut accepted = media_ranges(headers).peekable();
+    accepted.peek().is_none()
+        || accepted.any(|range| {
+            [NDJSON, "application/*", "*/*"]
+                .iter()
+                .any(|media_type| range.eq_ignore_ascii_case(media_type))
+        })
+}
+
+/// Whether the client names NDJSON itself, rather than only taking it.
+pub fn asks_for_ndjson(headers: &HeaderMap) -> bool {
+    media_ranges(headers).any(|range| range.eq_ignore_ascii_case(NDJSON))
+}
+
+fn lines(items: &[Item]) -> Vec<u8> {
+    let mut lines = vec![];
+    for item in items {
+        serde_json::to_writer(&mut lines, item).expect("item is serializable");
+        lines.push(b'\n');
+    }
+    lines
+}
+
+/// Streams the items of the `pages` of a parallel scan as NDJSON, each page
+/// as soon as it is read. The status is sent before the first item, so a scan
+/// that fails or does not get through the segments `progress` starts from
+/// instead cuts the body short with an error, which clients see as an
+/// incomplete transfer.
+pub fn ndjson_body(
+    pages: mpsc::Receiver<Result<SegmentPage, StoreError>>,
+    progress: ScanProgress,
+) -> Body {
+    let chunks = stream::unfold(Some((pages, progress)), |scan| async move {
+        let (mut pages, mut progress) = scan?;
+        match pages.recv().await {
+            Some(Ok(page)) => {
+                progress.advance(&page);
+                Some((Ok(lines(&page.items)), Some((pages, progress))))
+            }
+            Some(Err(e)) => {
+                tracing::error!("export failed: {:?}", e);
+                Some((Err(e), None))
+            }
+            None if progress.pending.is_empty() => None,
+            None => {
+                let e = StoreError::Backend("export ran out of time".into());
+                tracing::error!("export failed: {:?}", e);
+                Some((Err(e), None))
+            }
+        }
+    });
+    Body::from_stream(chunks)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::http::HeaderValue;
+
+    use super::*;
+
+    fn accept(values: &[&str]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(ACCEPT, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    fn accepts(values: &[&str]) -> bool {
+        accepts_ndjson(&accept(values))
+    }
+
+    fn asks(values: &[&str]) -> bool {
+        asks_for_ndjson(&accept(values))
+    }
+
+    #[test]
+    fn accepts_ndjson_unless_told_otherwise() {
+        assert!(accepts(&[]));
+        assert!(accepts(&["application/x-ndjson"]));
+        assert!(accepts(&["text/html, */*;q=0.8"]));
+        assert!(accepts(&["text/html", "Application/*"]));
+        assert!(!accepts(&["application/json"]));
+        assert!(!accepts(&["text/*"]));
+    }
+
+    #[test]
+    fn asks_for_ndjson_only_by_name() {
+        assert!(asks(&["application/json;q=0.5, Application/X-NDJSON"]));
+        assert!(asks(&["text/html", "application/x-ndjson;q=0.9"]));
+        assert!(!asks(&[]));
+        assert!(!asks(&["*/*"]));
+        assert!(!asks(&["application/*"]));
+    }
+
+    #[test]
+    fn writes_one_item_per_line() {
+        let items: Vec<Item> = (0..2)
+            .map(|n| serde_json::from_value(serde_json::json!({"n": n})).unwrap())
+            .collect();
+        assert_eq!(lines(&items), b"{\"n\":0}\n{\"n\":1}\n");
+    }
+}
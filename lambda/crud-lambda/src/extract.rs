@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+use axum::{
+    async_trait,
+    extract::{FromRequest, FromRequestParts, Path},
+    http::{request::Parts, Uri},
+    response::{IntoResponse, Response},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{error::ApiError, store::Key};
+
+/// `axum::Json` with rejections reported as `ApiError`.
+#[derive(FromRequest)]
+#[from_request(via(axum::Json), rejection(ApiError))]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Json<T> {
+    fn into_response(self) -> Response {
+        axum::Json(self.0).into_response()
+    }
+}
+
+/// `axum::extract::Query` with rejections reported as `ApiError`.
+#[derive(FromRequestParts)]
+#[from_request(via(axum::extract::Query), rejection(ApiError))]
+pub struct Query<T>(pub T);
+
+impl<T: DeserializeOwned> Query<T> {
+    /// Reads the parameters from `uri`, for handlers that only take them in
+    /// some cases.
+    pub fn try_from_uri(uri: &Uri) -> Result<Self, ApiError> {
+        let axum::extract::Query(params) = axum::extract::Query::try_from_uri(uri)?;
+        Ok(Query(params))
+    }
+}
+
+/// The key of the item an item route is for, from its `:id` and, on tables
+/// with a sort key, `:sk` path parameters.
+pub struct ItemKey(pub Key);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ItemKey {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let Path(mut params) =
+            Path::<HashMap<String, String>>::from_request_parts(parts, state).await?;
+        let id = params
+            .remove("id")
+            .ok_or_else(|| ApiError::BadRequest("missing item id".into()))?;
+        Ok(ItemKey(Key::new(id, params.remove("sk"))))
+    }
+}
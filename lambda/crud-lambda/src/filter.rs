@@ -0,0 +1,124 @@
+use serde_json::Value;
+
+use crate::{
+    error::ApiError,
+    store::{Comparison, Condition, DocumentPath},
+};
+
+/// Compiles the query parameters of a collection request into a filter.
+/// `field=value` asks for equality and `field[op]=value` for one of `eq`,
+/// `ne`, `gt`, `ge`, `lt`, `le`, `begins_with`, `contains`, `exists` and
+/// `not_exists`, all of which must hold. Parameters named in `reserved` are
+/// skipped.
+pub fn parse_filter(
+    query: &[(String, String)],
+    reserved: &[&str],
+) -> Result<Option<Condition>, ApiError> {
+    let mut conditions = vec![];
+
+    for (key, value) in query {
+        let (field, operator) = match key.strip_suffix(']').and_then(|key| key.split_once('[')) {
+            Some((field, operator)) => (field, operator),
+            None => (key.as_str(), "eq"),
+        };
+        if reserved.contains(&field) {
+            continue;
+        }
+        if field.is_empty() {
+            return Err(ApiError::BadRequest(format!(
+                "invalid filter parameter {:?}",
+                key
+            )));
+        }
+
+        let path = DocumentPath::attr(field);
+        let value = filter_value(value);
+        conditions.push(match operator {
+            "eq" => Condition::Equals(path, value),
+            "ne" => Condition::NotEquals(path, value),
+            "gt" => Condition::Compare(path, Comparison::Greater, value),
+            "ge" => Condition::Compare(path, Comparison::GreaterOrEqual, value),
+            "lt" => Condition::Compare(path, Comparison::Less, value),
+            "le" => Condition::Compare(path, Comparison::LessOrEqual, value),
+            "begins_with" => match value {
+                Value::String(prefix) => Condition::BeginsWith(path, prefix),
+                _ => return Err(ApiError::BadRequest(format!("{} needs a string", key))),
+            },
+            "contains" => Condition::Contains(path, value),
+            "exists" => Condition::Exists(path),
+            "not_exists" => Condition::NotExists(path),
+            _ => {
+                return Err(ApiError::BadRequest(format!(
+                    "unknown filter operator {:?}",
+                    operator
+                )))
+            }
+        });
+    }
+
+    Ok(Condition::all(conditions))
+}
+
+/// Query strings carry no types, so values that read as JSON numbers,
+/// booleans or quoted strings take that type; anything else is a string.
+fn filter_value(value: &str) -> Value {
+    match serde_json::from_str(value) {
+        Ok(value @ (Value::Number(_) | Value::Bool(_) | Value::String(_))) => value,
+        _ => Value::String(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::store::Item;
+
+    fn filter(query: &str) -> Result<Option<Condition>, ApiError> {
+        let query: Vec<(String, String)> = serde_urlencoded::from_str(query).unwrap();
+        parse_filter(&query, &["limit"])
+    }
+
+    fn matches(query: &str, item: Value) -> bool {
+        let item: Item = serde_json::from_value(item).unwrap();
+        filter(query).unwrap().unwrap().matches(Some(&item))
+    }
+
+    #[test]
+    fn compiles_operators() {
+        let item = json!({"status": "open", "n": 5, "tags": ["a"], "flag": true, "code": "42"});
+
+        assert!(matches("status=open&n=5", item.clone()));
+        assert!(matches("n[gt]=4&n[le]=5&n[ne]=6", item.clone()));
+        assert!(matches(
+            "status[begins_with]=op&tags[contains]=a",
+            item.clone()
+        ));
+        assert!(matches(
+            "flag=true&missing[not_exists]=&n[exists]=",
+            item.clone()
+        ));
+        assert!(matches("code=%2242%22", item.clone()));
+        assert!(!matches("code=42", item.clone()));
+        assert!(!matches("status=open&n[lt]=5", item));
+    }
+
+    #[test]
+    fn skips_reserved_parameters() {
+        assert!(filter("limit=5").unwrap().is_none());
+        assert!(filter("limit[gt]=5").unwrap().is_none());
+        assert!(filter("").unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        for query in ["n[between]=1", "[eq]=1", "n[begins_with]=1"] {
+            assert!(
+                matches!(filter(query), Err(ApiError::BadRequest(_))),
+                "{}",
+                query
+            );
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use axum::{
+    body::{to_bytes, Body},
+    http::{HeaderMap, HeaderName, HeaderValue, StatusCode},
+    response::{IntoResponse, Response},
+};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::ApiError,
+    store::{IdempotencyRecord, Item, RecordedResponse},
+};
+
+pub const IDEMPOTENCY_KEY: HeaderName = HeaderName::from_static("idempotency-key");
+pub const IDEMPOTENT_REPLAYED: HeaderName = HeaderName::from_static("idempotent-replayed");
+
+/// Longest `Idempotency-Key` accepted.
+const MAX_KEY_LEN: usize = 255;
+
+pub fn idempotency_key(headers: &HeaderMap) -> Result<Option<&str>, ApiError> {
+    let Some(value) = headers.get(IDEMPOTENCY_KEY) else {
+        return Ok(None);
+    };
+    match value.to_str() {
+        Ok(key) if !key.is_empty() && key.len() <= MAX_KEY_LEN => Ok(Some(key)),
+        _ => Err(ApiError::BadRequest(format!(
+            "Idempotency-Key must be 1 to {} visible ASCII characters",
+            MAX_KEY_LEN
+        ))),
+    }
+}
+
+/// Fingerprint of a request body, telling repeats of a request apart from
+/// different requests reusing its key. Hashing the parsed item ignores
+/// formatting and member order.
+pub fn request_hash(item: &Item) -> String {
+    let json = serde_json::to_vec(item).expect("item is serializable");
+    URL_SAFE_NO_PAD.encode(Sha256::digest(json))
+}
+
+/// Buffers `response` so it can be both sent and kept for replays.
+pub async fn record(response: Response) -> Result<(Response, RecordedResponse), ApiError> {
+    let (parts, body) = response.into_parts();
+    let bytes = to_bytes(body, usize::MAX)
+        .await
+        .map_err(|e| ApiError::Rejected(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+
+    let recorded = RecordedResponse {
+        status: parts.status.as_u16(),
+        headers: parts
+            .headers
+            .iter()
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect(),
+        body: String::from_utf8_lossy(&bytes).into_owned(),
+    };
+    Ok((Response::from_parts(parts, Body::from(bytes)), recorded))
+}
+
+/// Answers a repeat of a request whose key is already taken: with the first
+/// response if the request is the same and has finished.
+pub fn replay(record: IdempotencyRecord, request_hash: &str) -> Result<Response, ApiError> {
+    if record.request_hash != request_hash {
+        return Err(ApiError::Validation(
+            "Idempotency-Key was already used for a different request".into(),
+        ));
+    }
+    let Some(recorded) = record.response else {
+        return Err(ApiError::Conflict(
+            "a request with this Idempotency-Key is still in progress".into(),
+        ));
+    };
+
+    let mut response = recorded.body.into_response();
+    *response.status_mut() = StatusCode::from_u16(recorded.status).unwrap_or(StatusCode::OK);
+    let headers = response.headers_mut();
+    headers.clear();
+    for (name, value) in recorded.headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+            headers.append(name, value);
+        }
+    }
+    headers.insert(IDEMPOTENT_REPLAYED, HeaderValue::from_static("true"));
+    Ok(response)
+}
@@ -0,0 +1,12 @@
+use serde::Deserialize;
+
+#[derive(Debug, Deserialize)]
+pub struct IndexParams {
+    /// Name of a configured secondary index to query instead of scanning.
+    pub index: Option<String>,
+}
+
+impl IndexParams {
+    /// Query parameters taken by index selection rather than filtering.
+    pub const NAMES: &'static [&'static str] = &["index"];
+}
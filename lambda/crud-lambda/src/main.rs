@@ -1,28 +1,116 @@
-#![feature(lazy_cell)]
+mod conditional;
+mod config;
+mod error;
+mod export;
+mod extract;
+mod filter;
+mod idempotency;
+mod index;
+mod pagination;
+mod patch;
+mod projection;
+mod scan;
+mod sort_key;
+mod store;
+#[cfg(test)]
+mod tests;
+mod transaction;
 
-use std::{collections::HashMap, env::set_var, sync::LazyLock};
+use std::{
+    collections::{HashMap, HashSet},
+    env::set_var,
+    num::NonZeroU32,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use aws_config::BehaviorVersion;
-use aws_sdk_dynamodb::{types::AttributeValue, Client};
 use axum::{
-    extract::Path,
-    http::{Method, StatusCode},
-    routing::get,
-    Json, Router,
+    extract::{Request, State},
+    handler::Handler,
+    http::{
+        header::{
+            HeaderName, CONTENT_TYPE, ETAG, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH,
+            LAST_MODIFIED, LINK, LOCATION, VARY,
+        },
+        HeaderMap, HeaderValue, Method, StatusCode, Uri,
+    },
+    middleware::map_response,
+    response::{AppendHeaders, IntoResponse, Response},
+    routing::{get, post, MethodRouter},
+    Extension, Router,
+};
+use conditional::{
+    if_match, if_none_match_any, not_modified, now, parse_if_match, unchanged, validators, version,
+    IfMatch, UPDATED_AT, VERSION,
+};
+use config::{Backend, Config};
+use error::ApiError;
+use export::{accepts_ndjson, asks_for_ndjson, ndjson_body, NDJSON};
+use extract::{ItemKey, Json, Query};
+use filter::parse_filter;
+use idempotency::{
+    idempotency_key, record, replay, request_hash, IDEMPOTENCY_KEY, IDEMPOTENT_REPLAYED,
 };
-use lambda_http::{run, Error};
-use serde_dynamo::aws_sdk_dynamodb_1::{from_item, from_items, to_attribute_value, to_item};
+use index::IndexParams;
+use lambda_http::{run, run_with_streaming_response, Context, Error};
+use pagination::{decode_cursor, next_link, PageParams};
+use patch::{json_patch, merge_patch, operators, reads_current, PatchBody};
+use projection::{include, FieldsParams};
+use scan::{
+    export_deadline, parallel_scan, scan_deadline, ScanParams, ScanProgress, MAX_RESPONSE_BYTES,
+    MAX_SEGMENTS,
+};
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use sort_key::{Order, SortKeyParams};
+use store::{
+    project, Condition, DocumentPath, DynamoIdempotencyStore, DynamoStore, IdempotencyStore, Item,
+    ItemStore, Key, KeyQuery, KeySchema, MemoryIdempotencyStore, MemoryStore, Operand, Page,
+    PageRequest, ReturnValues, StoreError, TransactWrite, Update, Write,
+};
 use tower_http::cors::{Any, CorsLayer};
 use tracing_subscriber::filter::{EnvFilter, LevelFilter};
+use transaction::{OperationKind, TransactionOperation, TransactionRequest, MAX_OPERATIONS};
+
+const PREFER: HeaderName = HeaderName::from_static("prefer");
+const PREFERENCE_APPLIED: HeaderName = HeaderName::from_static("preference-applied");
+const DEPRECATION: HeaderName = HeaderName::from_static("deprecation");
 
-static TABLE_NAME: LazyLock<String> =
-    LazyLock::new(|| std::env::var("TABLE_NAME").expect("TABLE_NAME must be set"));
-static PK: LazyLock<String> = LazyLock::new(|| std::env::var("PK").expect("PK must be set"));
+#[derive(Clone)]
+struct AppState {
+    store: Arc<dyn ItemStore>,
+    idempotency: Option<Arc<dyn IdempotencyStore>>,
+    config: Arc<Config>,
+}
+
+impl AppState {
+    async fn new(config: Config) -> Self {
+        let (store, idempotency): (Arc<dyn ItemStore>, Option<Arc<dyn IdempotencyStore>>) =
+            match config.backend {
+                Backend::Memory => (
+                    Arc::new(MemoryStore::new(config.keys())),
+                    Some(Arc::new(MemoryIdempotencyStore::new())),
+                ),
+                Backend::Dynamo => {
+                    let sdk_config = config.dynamodb.load().await;
+                    let client = aws_sdk_dynamodb::Client::new(&sdk_config);
+                    let idempotency = config.idempotency_table.as_ref().map(|table_name| {
+                        Arc::new(DynamoIdempotencyStore::new(client.clone(), table_name))
+                            as Arc<dyn IdempotencyStore>
+                    });
+                    (
+                        Arc::new(DynamoStore::new(client, &config.table_name, config.keys())),
+                        idempotency,
+                    )
+                }
+            };
 
-async fn dynamo() -> Client {
-    let config = aws_config::load_defaults(BehaviorVersion::latest()).await;
-    aws_sdk_dynamodb::Client::new(&config)
+        Self {
+            store,
+            idempotency,
+            config: Arc::new(config),
+        }
+    }
 }
 
 #[tokio::main]
@@ -40,156 +128,1121 @@ async fn main() -> Result<(), Error> {
         .init();
 
     let cors = CorsLayer::new()
-        .allow_methods([Method::GET, Method::POST, Method::DELETE, Method::PATCH])
-        .allow_origin(Any);
+        .allow_methods([
+            Method::GET,
+            Method::POST,
+            Method::PUT,
+            Method::DELETE,
+            Method::PATCH,
+        ])
+        .allow_origin(Any)
+        .allow_headers([
+            CONTENT_TYPE,
+            IF_MATCH,
+            IF_MODIFIED_SINCE,
+            IF_NONE_MATCH,
+            PREFER,
+            IDEMPOTENCY_KEY,
+        ])
+        .expose_headers([
+            ETAG,
+            LAST_MODIFIED,
+            LINK,
+            LOCATION,
+            PREFERENCE_APPLIED,
+            DEPRECATION,
+            IDEMPOTENT_REPLAYED,
+        ]);
+
+    let state = AppState::new(Config::from_env()?).await;
+    let config = state.config.clone();
 
-    let app = Router::new()
-        .route("/items", get(get_all).post(create))
-        .route("/:id", get(get_one).delete(delete_one).patch(update_one))
-        .layer(cors);
+    let app = router(&config).layer(cors).with_state(state);
 
+    if let Some(addr) = config.local_addr {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        tracing::info!("listening on {}", addr);
+        return Ok(axum::serve(listener, app).await?);
+    }
+    if config.response_streaming {
+        return run_with_streaming_response(app).await;
+    }
     run(app).await
 }
 
-async fn create(Json(mut body): Json<Value>) -> Result<(), StatusCode> {
-    let client = dynamo().await;
+fn item_routes() -> MethodRouter<AppState> {
+    get(get_one)
+        .put(replace_one)
+        .delete(delete_one)
+        .patch(update_one)
+}
+
+fn router(config: &Config) -> Router<AppState> {
+    // A streaming deployment sits apart from the API and only exports.
+    if config.response_streaming {
+        return Router::new().route("/items", get(export));
+    }
+
+    let mut router = Router::new()
+        .route("/items", get(get_items).post(create))
+        .route("/transactions", post(transact));
+
+    // With a sort key, an item takes both keys to name, and the partition
+    // key alone names the items sharing it.
+    router = match config.sk {
+        Some(_) => router
+            .route("/items/:id", get(query_items))
+            .route("/items/:id/:sk", item_routes()),
+        None => router.route("/items/:id", item_routes()),
+    };
+
+    if config.legacy_routes && config.sk.is_none() {
+        router = router.route("/:id", item_routes().layer(map_response(deprecated)));
+    }
+
+    router
+}
+
+/// Marks responses from the legacy `/:id` routes as deprecated.
+async fn deprecated(mut response: Response) -> Response {
+    response
+        .headers_mut()
+        .insert(DEPRECATION, HeaderValue::from_static("true"));
+    response
+}
+
+/// Whether the client sent `Prefer: return=minimal` (RFC 7240).
+fn prefers_minimal(headers: &HeaderMap) -> bool {
+    headers
+        .get_all(PREFER)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split([',', ';']))
+        .any(|preference| preference.trim().eq_ignore_ascii_case("return=minimal"))
+}
+
+/// Gives a new item its first version, returning its key. Without a sort key
+/// the item gets a generated id; with one, the item brings both keys.
+fn prepare_new(keys: &KeySchema, item: &mut Item) -> Result<Key, ApiError> {
+    let key = match &keys.sk {
+        None => {
+            let id = uuid::Uuid::new_v4().to_string();
+            item.insert(keys.pk.clone(), Value::String(id.clone()));
+            Key::new(id, None)
+        }
+        Some(sk) => keys.key_of(item).ok_or_else(|| {
+            ApiError::Validation(format!("{} and {} must be strings", keys.pk, sk))
+        })?,
+    };
+    item.insert(VERSION.to_string(), Value::from(1));
+    item.insert(UPDATED_AT.to_string(), now());
+    Ok(key)
+}
+
+/// Condition for creating an item. Generated ids are new, but keys a client
+/// chose may already be taken.
+fn create_condition(config: &Config) -> Option<Condition> {
+    config
+        .sk
+        .is_some()
+        .then(|| Condition::NotExists(DocumentPath::attr(&config.pk)))
+}
+
+/// Checks that a key from a request body has a sort key exactly when the
+/// table does.
+fn check_key(config: &Config, key: &Key) -> Result<(), ApiError> {
+    match (&config.sk, &key.sk) {
+        (Some(_), None) => Err(ApiError::Validation(format!(
+            "key {:?} is missing its sort key",
+            key.id
+        ))),
+        (None, Some(_)) => Err(ApiError::Validation(format!(
+            "key {:?} has a sort key, but the table has none",
+            key.id
+        ))),
+        _ => Ok(()),
+    }
+}
 
-    body.as_object_mut()
-        .expect("body must be an object")
-        .insert(
-            PK.to_string(),
-            Value::String(uuid::Uuid::new_v4().to_string()),
-        );
+/// Creates an item, or applies a batch of writes if the body is one. With an
+/// `Idempotency-Key`, the first response for the key is recorded and replayed
+/// for repeats of the request instead of writing again.
+async fn create(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(item): Json<Item>,
+) -> Result<Response, ApiError> {
+    let (Some(key), Some(idempotency)) = (idempotency_key(&headers)?, &state.idempotency) else {
+        return create_item(&state, &headers, item).await;
+    };
+
+    // Hold the key only briefly until there is a response, so that a request
+    // that never finishes does not block its retries for the whole TTL.
+    let request_hash = request_hash(&item);
+    let lock_expires_at = expires_at(state.config.idempotency_lock);
+    if let Some(record) = idempotency
+        .claim(key, &request_hash, lock_expires_at)
+        .await?
+    {
+        return replay(record, &request_hash);
+    }
+
+    match create_item(&state, &headers, item).await {
+        Ok(response) => {
+            let (response, recorded) = record(response).await?;
+            let expires_at = expires_at(state.config.idempotency_ttl);
+            if let Err(e) = idempotency.record(key, &recorded, expires_at).await {
+                tracing::error!("failed to record idempotent response: {:?}", e);
+            }
+            Ok(response)
+        }
+        Err(e) => {
+            if let Err(e) = idempotency.release(key).await {
+                tracing::error!("failed to release idempotency key: {:?}", e);
+            }
+            Err(e)
+        }
+    }
+}
 
-    let _ = client
-        .put_item()
-        .table_name(TABLE_NAME.to_string())
-        .set_item(to_item(body).ok())
-        .send()
+/// Seconds since the epoch at which something kept for `ttl` from now
+/// expires.
+fn expires_at(ttl: Duration) -> u64 {
+    (SystemTime::now() + ttl)
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+async fn create_item(
+    state: &AppState,
+    headers: &HeaderMap,
+    mut item: Item,
+) -> Result<Response, ApiError> {
+    if is_batch(&item) {
+        let request = serde_json::from_value(Value::Object(item))
+            .map_err(|e| ApiError::Validation(e.to_string()))?;
+        return Ok(batch_write(state, request).await?.into_response());
+    }
+
+    let key = prepare_new(&state.config.keys(), &mut item)?;
+
+    match state
+        .store
+        .put(item.clone(), create_condition(&state.config))
         .await
-        .map_err(|e| {
-            tracing::error!("error: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
+    {
+        Err(StoreError::ConditionFailed) => {
+            return Err(ApiError::Conflict("item already exists".into()))
+        }
+        result => result?,
+    }
+
+    let location = [(LOCATION, format!("/items/{}", key.path()))];
+    let validators = AppendHeaders(validators(&item));
+    if prefers_minimal(headers) {
+        return Ok((
+            StatusCode::CREATED,
+            location,
+            validators,
+            [(PREFERENCE_APPLIED, "return=minimal")],
+        )
+            .into_response());
+    }
+
+    Ok((StatusCode::CREATED, location, validators, Json(item)).into_response())
+}
+
+async fn get_one(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ItemKey(key): ItemKey,
+    Query(fields): Query<FieldsParams>,
+) -> Result<Response, ApiError> {
+    // Read the validators along with the projection, then leave them out of
+    // the body unless they were asked for.
+    let projection = fields.projection(&state.config.keys())?;
+    let read = projection.clone().map(|mut paths| {
+        include(&mut paths, DocumentPath::attr(VERSION));
+        include(&mut paths, DocumentPath::attr(UPDATED_AT));
+        paths
+    });
+    let mut item = state
+        .store
+        .get(&key, read.as_deref())
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    let validators = AppendHeaders(validators(&item));
+    if not_modified(&headers, &item) {
+        return Ok((StatusCode::NOT_MODIFIED, validators).into_response());
+    }
+    if let Some(paths) = &projection {
+        for attr in [VERSION, UPDATED_AT] {
+            if !paths.contains(&DocumentPath::attr(attr)) {
+                item.remove(attr);
+            }
+        }
+    }
+
+    Ok((validators, Json(item)).into_response())
+}
+
+fn query_pairs(uri: &Uri) -> Result<Vec<(String, String)>, ApiError> {
+    Ok(uri
+        .query()
+        .map(serde_urlencoded::from_str)
+        .transpose()
+        .map_err(|_| ApiError::BadRequest("invalid query string".into()))?
+        .unwrap_or_default())
+}
+
+/// Reads the paging, projection and filter parameters of a scan or query.
+/// `reserved` lists the other parameters the route takes, which are not
+/// filters either.
+fn page_request(
+    config: &Config,
+    uri: &Uri,
+    params: PageParams,
+    fields: FieldsParams,
+    reserved: &[&str],
+) -> Result<PageRequest, ApiError> {
+    let start_key = params
+        .cursor
+        .map(|cursor| {
+            decode_cursor(&cursor).ok_or_else(|| ApiError::BadRequest("invalid cursor".into()))
+        })
+        .transpose()?;
+
+    let filter = parse_filter(
+        &query_pairs(uri)?,
+        &[PageParams::NAMES, FieldsParams::NAMES, reserved].concat(),
+    )?;
+
+    Ok(PageRequest {
+        limit: params.limit.map(usize::from),
+        start_key,
+        filter,
+        projection: fields.projection(&config.keys())?,
+        segment: None,
+    })
+}
+
+/// Responds with the items of `page`, linking to the next one if there is.
+fn page_response(uri: &Uri, page: Page) -> impl IntoResponse {
+    let link = page
+        .last_key
+        .map(|last_key| (LINK, next_link(uri, &last_key)));
+
+    (AppendHeaders(link), Json(page.items))
+}
+
+/// Lists items. With `?index=`, queries that secondary index for the items
+/// whose index partition key equals the query parameter named after it, such
+/// as `?index=byStatus&status=open`; otherwise scans the table, in parallel
+/// segments with `?segments=`.
+async fn get_all(
+    State(state): State<AppState>,
+    uri: Uri,
+    Query(params): Query<PageParams>,
+    Query(fields): Query<FieldsParams>,
+    Query(index): Query<IndexParams>,
+    Query(scan): Query<ScanParams>,
+    context: Option<Extension<Context>>,
+) -> Result<Response, ApiError> {
+    let Some(name) = index.index else {
+        let reserved = [IndexParams::NAMES, ScanParams::NAMES].concat();
+        let request = page_request(&state.config, &uri, params, fields, &reserved)?;
+        let Some(segments) = scan.segments else {
+            let page = state.store.scan(request).await?;
+            return Ok(page_response(&uri, page).into_response());
+        };
+        let context = context.as_ref().map(|Extension(context)| context);
+        return parallel_get_all(&state, &uri, request, segments, context).await;
+    };
+    if scan.segments.is_some() {
+        return Err(ApiError::BadRequest(
+            "segments cannot be combined with index".into(),
+        ));
+    }
+
+    let index = state
+        .config
+        .index(&name)
+        .ok_or_else(|| ApiError::BadRequest(format!("unknown index {:?}", name)))?
+        .clone();
+    let id = query_pairs(&uri)?
+        .into_iter()
+        .find(|(k, _)| *k == index.keys.pk)
+        .map(|(_, v)| v)
+        .ok_or_else(|| {
+            ApiError::BadRequest(format!(
+                "querying index {} requires ?{}=",
+                index.name, index.keys.pk
+            ))
         })?;
+    // Sort key parameters only mean something to index queries; scans take
+    // them as filters like any other attribute.
+    let Query(sort) = Query::<SortKeyParams>::try_from_uri(&uri)?;
+    let condition = sort.condition()?;
+    if condition.is_some() && index.keys.sk.is_none() {
+        return Err(ApiError::BadRequest(format!(
+            "index {} has no sort key",
+            index.name
+        )));
+    }
+
+    let reserved = [
+        IndexParams::NAMES,
+        SortKeyParams::NAMES,
+        &[index.keys.pk.as_str()],
+    ]
+    .concat();
+    let request = page_request(&state.config, &uri, params, fields, &reserved)?;
+    let query = KeyQuery {
+        index: Some(index),
+        id,
+        sort: condition,
+        forward: sort.order == Order::Asc,
+    };
 
-    Ok(())
+    let page = state.store.query(query, request).await?;
+    Ok(page_response(&uri, page).into_response())
 }
 
-async fn get_one(Path(id): Path<String>) -> Result<Json<Value>, StatusCode> {
-    let client = dynamo().await;
-    let item = client
-        .get_item()
-        .table_name(TABLE_NAME.to_string())
-        .key(PK.to_string(), AttributeValue::S(id))
-        .send()
-        .await
-        .map_err(|e| {
-            tracing::error!("error: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?
-        .item
-        .expect("item not found");
-
-    Ok(Json(from_item(item).ok().unwrap()))
-}
-
-async fn get_all() -> Result<Json<Vec<Value>>, StatusCode> {
-    let client = dynamo().await;
-    let items = client
-        .scan()
-        .table_name(TABLE_NAME.to_string())
-        .send()
-        .await
-        .map_err(|e| {
-            tracing::error!("error: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?
-        .items
-        .expect("items not found");
-
-    Ok(Json(from_items(items).ok().unwrap()))
-}
-
-async fn delete_one(Path(id): Path<String>) -> Result<(), StatusCode> {
-    let client = dynamo().await;
-    let _ = client
-        .delete_item()
-        .table_name(TABLE_NAME.to_string())
-        .key(PK.to_string(), AttributeValue::S(id))
-        .send()
+/// Scans the table in `segments` segments at once, handing back a cursor for
+/// the segments left unfinished. With `?limit=`, one page is read from each
+/// of up to `limit` segments; otherwise reading stops as the time budget runs
+/// out. Either way, no more pages are taken once the next would take the
+/// response past `MAX_RESPONSE_BYTES`. The cursor of a parallel scan holds
+/// the progress of each segment rather than a key.
+async fn parallel_get_all(
+    state: &AppState,
+    uri: &Uri,
+    mut request: PageRequest,
+    segments: NonZeroU32,
+    context: Option<&Context>,
+) -> Result<Response, ApiError> {
+    let segments = segments.get();
+    if segments > MAX_SEGMENTS {
+        return Err(ApiError::BadRequest(format!(
+            "segments must be at most {}",
+            MAX_SEGMENTS
+        )));
+    }
+    let mut progress = match request.start_key.take() {
+        Some(cursor) => serde_json::from_value::<ScanProgress>(Value::Object(cursor))
+            .ok()
+            .filter(|progress| progress.fits(segments))
+            .ok_or_else(|| ApiError::BadRequest("invalid cursor".into()))?,
+        None => ScanProgress::new(segments),
+    };
+
+    // Split a limit between the segments read, so that every page read fits
+    // in the response. Pages are taken whole, so that each segment resumes
+    // right after the last page taken; any read but not taken are read again
+    // on resuming.
+    let (scan, pages_per_segment) = match request.limit {
+        Some(limit) => {
+            let scan = progress.take(limit);
+            request.limit = Some(limit / scan.pending.len().max(1));
+            (scan, Some(1))
+        }
+        None => (progress.clone(), None),
+    };
+    let mut pages = parallel_scan(
+        state.store.clone(),
+        scan,
+        request,
+        state.config.scan_concurrency.get(),
+        Some(scan_deadline(&state.config, context)),
+        pages_per_segment,
+    );
+    let (mut items, mut size) = (vec![], 0);
+    while let Some(page) = pages.recv().await {
+        let page = page?;
+        let page_size: usize = page
+            .items
+            .iter()
+            .map(|item| serde_json::to_vec(item).map_or(0, |json| json.len()))
+            .sum();
+        if !items.is_empty() && size + page_size > MAX_RESPONSE_BYTES {
+            break;
+        }
+        progress.advance(&page);
+        size += page_size;
+        items.extend(page.items);
+    }
+
+    let link = (!progress.pending.is_empty()).then(|| (LINK, next_link(uri, &progress)));
+    Ok((AppendHeaders(link), Json(items)).into_response())
+}
+
+/// Lists the items sharing a partition key, on tables with a sort key, in
+/// sort key order.
+async fn query_items(
+    State(state): State<AppState>,
+    uri: Uri,
+    ItemKey(key): ItemKey,
+    Query(sort): Query<SortKeyParams>,
+    Query(params): Query<PageParams>,
+    Query(fields): Query<FieldsParams>,
+) -> Result<impl IntoResponse, ApiError> {
+    let query = KeyQuery {
+        index: None,
+        id: key.id,
+        sort: sort.condition()?,
+        forward: sort.order == Order::Asc,
+    };
+    let request = page_request(&state.config, &uri, params, fields, SortKeyParams::NAMES)?;
+    let page = state.store.query(query, request).await?;
+
+    Ok(page_response(&uri, page))
+}
+
+/// Streams every item as NDJSON, reading the table in `?segments=` parallel
+/// segments, one by default. Unlike listings, the items are not held in
+/// memory, so with `RESPONSE_STREAMING` exports are not bound by the response
+/// size limit; buffered, they fail once the table outgrows it.
+async fn export(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    uri: Uri,
+    Query(scan): Query<ScanParams>,
+    context: Option<Extension<Context>>,
+) -> Result<Response, ApiError> {
+    if !accepts_ndjson(&headers) {
+        return Err(ApiError::Rejected(
+            StatusCode::NOT_ACCEPTABLE,
+            format!("exports are only available as {}", NDJSON),
+        ));
+    }
+    if let Some((name, _)) = query_pairs(&uri)?
+        .into_iter()
+        .find(|(k, _)| !ScanParams::NAMES.contains(&k.as_str()))
+    {
+        return Err(ApiError::BadRequest(format!(
+            "exports cannot be combined with {}",
+            name
+        )));
+    }
+    let segments = scan.segments.map_or(1, NonZeroU32::get);
+    if segments > MAX_SEGMENTS {
+        return Err(ApiError::BadRequest(format!(
+            "segments must be at most {}",
+            MAX_SEGMENTS
+        )));
+    }
+
+    let context = context.as_ref().map(|Extension(context)| context);
+    let progress = ScanProgress::new(segments);
+    let pages = parallel_scan(
+        state.store.clone(),
+        progress.clone(),
+        PageRequest::default(),
+        state.config.scan_concurrency.get(),
+        export_deadline(&state.config, context),
+        None,
+    );
+
+    Ok(([(CONTENT_TYPE, NDJSON)], ndjson_body(pages, progress)).into_response())
+}
+
+/// Query parameters taken by batch gets rather than filtering.
+const BATCH_GET_NAMES: &[&str] = &["ids"];
+
+/// The raw values of `?ids=`, left percent-encoded so that commas and slashes
+/// in key values stay apart from the separators.
+fn ids_param(uri: &Uri) -> Vec<&str> {
+    uri.query()
+        .into_iter()
+        .flat_map(|query| query.split('&'))
+        .filter_map(|pair| match pair.split_once('=') {
+            Some((name, value)) => (name == "ids").then_some(value),
+            None => (pair == "ids").then_some(""),
+        })
+        .collect()
+}
+
+/// Serves `GET /items`: exports every item to clients asking for NDJSON,
+/// fetches the items `?ids=` lists if there is one, and lists items
+/// otherwise.
+async fn get_items(State(state): State<AppState>, request: Request) -> Response {
+    let mut response = if asks_for_ndjson(request.headers()) {
+        export.call(request, state).await
+    } else if !ids_param(request.uri()).is_empty() {
+        batch_get.call(request, state).await
+    } else {
+        get_all.call(request, state).await
+    };
+    response
+        .headers_mut()
+        .append(VARY, HeaderValue::from_static("accept"));
+    response
+}
+
+#[derive(Debug, Serialize)]
+struct BatchGetResponse {
+    items: Vec<Item>,
+    missing: Vec<Key>,
+}
+
+/// Fetches the items `?ids=a,b,c` lists at once, each key written as in item
+/// routes (`id` or `id/sk`, percent-encoded). Items come back in the order
+/// their keys were asked for, and keys without an item are listed as
+/// missing. Filters and `?fields=` apply as in listings; items the filters
+/// leave out are not missing.
+async fn batch_get(
+    State(state): State<AppState>,
+    uri: Uri,
+    Query(fields): Query<FieldsParams>,
+) -> Result<Json<BatchGetResponse>, ApiError> {
+    let mut keys = vec![];
+    for path in ids_param(&uri).into_iter().flat_map(|ids| ids.split(',')) {
+        let key = Key::from_path(path)
+            .ok_or_else(|| ApiError::BadRequest(format!("invalid id {:?}", path)))?;
+        check_key(&state.config, &key)?;
+        keys.push(key);
+    }
+    let listing = [PageParams::NAMES, IndexParams::NAMES, ScanParams::NAMES].concat();
+    let pairs = query_pairs(&uri)?;
+    if let Some((name, _)) = pairs.iter().find(|(k, _)| listing.contains(&k.as_str())) {
+        return Err(ApiError::BadRequest(format!(
+            "ids cannot be combined with {}",
+            name
+        )));
+    }
+    let filter = parse_filter(&pairs, &[BATCH_GET_NAMES, FieldsParams::NAMES].concat())?;
+    let projection = fields.projection(&state.config.keys())?;
+    let mut seen = HashSet::new();
+    keys.retain(|key| seen.insert(key.clone()));
+
+    let schema = state.config.keys();
+    let mut found: HashMap<Key, Item> = state
+        .store
+        .batch_get(&keys)
+        .await?
+        .into_iter()
+        .filter_map(|item| Some((schema.key_of(&item)?, item)))
+        .collect();
+
+    let mut response = BatchGetResponse {
+        items: vec![],
+        missing: vec![],
+    };
+    for key in keys {
+        match found.remove(&key) {
+            Some(item)
+                if filter
+                    .as_ref()
+                    .is_some_and(|filter| !filter.matches(Some(&item))) => {}
+            Some(item) => response.items.push(match &projection {
+                Some(paths) => project(&item, paths),
+                None => item,
+            }),
+            None => response.missing.push(key),
+        }
+    }
+
+    Ok(Json(response))
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchWriteRequest {
+    #[serde(default)]
+    put: Vec<Item>,
+    #[serde(default)]
+    delete: Vec<Key>,
+}
+
+/// Outcome of one write of a batch or transaction, with the status the
+/// single-item route would have answered.
+#[derive(Debug, Serialize)]
+struct WriteResult {
+    id: Key,
+    status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct BatchWriteResponse {
+    put: Vec<WriteResult>,
+    delete: Vec<WriteResult>,
+}
+
+/// Whether a `POST /items` body is a batch of writes rather than an item,
+/// which it is when it holds nothing but `put` and `delete` lists.
+fn is_batch(body: &Item) -> bool {
+    !body.is_empty()
+        && body
+            .iter()
+            .all(|(k, v)| ["put", "delete"].contains(&k.as_str()) && v.is_array())
+}
+
+/// Creates and deletes several items at once. Writes are unconditional and
+/// succeed or fail one by one, so the response lists an outcome for each, in
+/// request order, including the id generated for every created item. On
+/// tables with a sort key, puts replace any item with the same key.
+async fn batch_write(
+    state: &AppState,
+    request: BatchWriteRequest,
+) -> Result<Json<BatchWriteResponse>, ApiError> {
+    let schema = state.config.keys();
+    let mut keys = vec![];
+    let mut writes = vec![];
+    for mut item in request.put {
+        keys.push(prepare_new(&schema, &mut item)?);
+        writes.push(Write::Put(item));
+    }
+    let puts = keys.len();
+    for key in request.delete {
+        check_key(&state.config, &key)?;
+        keys.push(key.clone());
+        writes.push(Write::Delete(key));
+    }
+
+    let mut seen = HashSet::new();
+    if !keys.iter().all(|key| seen.insert(key)) {
+        return Err(ApiError::Validation(
+            "a batch must not write to an item more than once".into(),
+        ));
+    }
+
+    let mut results: Vec<_> = state
+        .store
+        .batch_write(writes)
         .await
-        .map_err(|e| {
-            tracing::error!("error: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        .into_iter()
+        .zip(keys)
+        .enumerate()
+        .map(|(i, (outcome, id))| match outcome {
+            Ok(()) if i < puts => WriteResult {
+                id,
+                status: StatusCode::CREATED.as_u16(),
+                detail: None,
+            },
+            Ok(()) => WriteResult {
+                id,
+                status: StatusCode::OK.as_u16(),
+                detail: None,
+            },
+            Err(e) => {
+                let e = ApiError::from(e);
+                if let ApiError::Internal(e) = &e {
+                    tracing::error!("error: {:?}", e);
+                }
+                WriteResult {
+                    id,
+                    status: e.status().as_u16(),
+                    detail: Some(e.to_string()),
+                }
+            }
+        })
+        .collect();
+
+    let delete = results.split_off(puts);
+    Ok(Json(BatchWriteResponse {
+        put: results,
+        delete,
+    }))
+}
 
-    Ok(())
+#[derive(Debug, Serialize)]
+struct TransactionResponse {
+    operations: Vec<WriteResult>,
 }
 
-async fn update_one(Path(id): Path<String>, Json(body): Json<Value>) -> Result<(), StatusCode> {
-    let client = dynamo().await;
+/// Applies several writes atomically. When the transaction is canceled, the
+/// problem document lists why each operation failed, if it did.
+async fn transact(
+    State(state): State<AppState>,
+    Json(request): Json<TransactionRequest>,
+) -> Result<Json<TransactionResponse>, ApiError> {
+    let operations = request.operations;
+    if operations.is_empty() || operations.len() > MAX_OPERATIONS {
+        return Err(ApiError::Validation(format!(
+            "a transaction must hold between 1 and {} operations",
+            MAX_OPERATIONS
+        )));
+    }
 
-    let (update, remove, expression_attribute_name, expression_attribute_value) = body
-        .as_object()
-        .expect("body must be an object")
+    let pk = &state.config.pk;
+    let schema = state.config.keys();
+    let if_matches: Vec<_> = operations
         .iter()
-        .fold(
-            (vec![], vec![], HashMap::new(), HashMap::new()),
-            |(
-                mut update,
-                mut remove,
-                mut expression_attribute_name,
-                mut expression_attribute_value,
-            ),
-             (k, v)| {
-                if v.is_null() {
-                    remove.push(format!("#{}", k));
+        .map(TransactionOperation::if_match)
+        .collect();
+    let kinds: Vec<_> = operations.iter().map(TransactionOperation::kind).collect();
+    let mut results = vec![];
+    let mut writes = vec![];
+    for (operation, if_match) in operations.into_iter().zip(if_matches.iter().copied()) {
+        if let Some(key) = operation.key() {
+            check_key(&state.config, &key)?;
+        }
+        let (id, status, write) = match operation {
+            TransactionOperation::Put { mut item } => {
+                let key = prepare_new(&schema, &mut item)?;
+                let write = TransactWrite::Put {
+                    item,
+                    condition: create_condition(&state.config),
+                };
+                (key, StatusCode::CREATED, write)
+            }
+            TransactionOperation::Update {
+                id,
+                sk,
+                patch,
+                upsert,
+                ..
+            } => {
+                let key = Key::new(id, sk);
+                let (mut update, conditions) =
+                    compile_patch(&state, &key, patch, upsert, if_match).await?;
+                // A patch made only of tests becomes a check of the item.
+                let write = if update.is_empty() {
+                    TransactWrite::Check {
+                        key: key.clone(),
+                        condition: Condition::all(conditions)
+                            .unwrap_or_else(|| Condition::Exists(DocumentPath::attr(pk))),
+                    }
                 } else {
-                    update.push(format!("#{} = :{}", k, k));
-                    expression_attribute_value
-                        .insert(format!(":{}", k), to_attribute_value(v).ok().unwrap());
-                }
-                expression_attribute_name.insert(format!("#{}", k), k.to_string());
-                (
-                    update,
-                    remove,
-                    expression_attribute_name,
-                    expression_attribute_value,
-                )
-            },
-        );
+                    next_version(&mut update);
+                    TransactWrite::Update {
+                        key: key.clone(),
+                        update,
+                        condition: Condition::all(conditions),
+                    }
+                };
+                (key, StatusCode::OK, write)
+            }
+            TransactionOperation::Delete { id, sk, .. } => {
+                let key = Key::new(id, sk);
+                let write = TransactWrite::Delete {
+                    key: key.clone(),
+                    condition: if_match.map(|if_match| if_match.condition(pk)),
+                };
+                (key, StatusCode::OK, write)
+            }
+            TransactionOperation::Check { id, sk, .. } => {
+                let key = Key::new(id, sk);
+                let write = TransactWrite::Check {
+                    key: key.clone(),
+                    condition: if_match.unwrap_or(IfMatch::Any).condition(pk),
+                };
+                (key, StatusCode::OK, write)
+            }
+        };
+        results.push(WriteResult {
+            id,
+            status: status.as_u16(),
+            detail: None,
+        });
+        writes.push(write);
+    }
 
-    let update_expression = if !update.is_empty() {
-        format!("SET {} ", update.join(", "))
-    } else {
-        "".into()
+    let reasons = match state.store.transact_write(writes).await {
+        Ok(()) => {
+            return Ok(Json(TransactionResponse {
+                operations: results,
+            }))
+        }
+        Err(StoreError::Canceled(reasons)) => reasons,
+        Err(e) => return Err(e.into()),
     };
 
-    let remove_expression = if !remove.is_empty() {
-        format!("REMOVE {} ", remove.join(", "))
-    } else {
-        "".into()
+    let mut errors = vec![];
+    for (((reason, result), kind), if_match) in
+        reasons.into_iter().zip(&results).zip(kinds).zip(if_matches)
+    {
+        errors.push(match reason {
+            Some(StoreError::ConditionFailed) => {
+                Some(condition_failed(&state, kind, &result.id, if_match).await)
+            }
+            reason => reason.map(ApiError::from),
+        });
+    }
+    Err(ApiError::Canceled(errors))
+}
+
+/// Works out why the condition of a transaction operation failed, answering
+/// as the item route for the same write would: a put finds its key taken, an
+/// update fails like a PATCH, and otherwise the client's copy is stale or,
+/// without a version to match, the item does not exist.
+async fn condition_failed(
+    state: &AppState,
+    kind: OperationKind,
+    key: &Key,
+    if_match: Option<IfMatch>,
+) -> ApiError {
+    match (kind, if_match) {
+        (OperationKind::Put, _) => ApiError::Conflict("item already exists".into()),
+        (OperationKind::Update { reads_current }, _) => {
+            let e = StoreError::ConditionFailed;
+            update_failed(state, key, e, if_match, reads_current).await
+        }
+        (OperationKind::Delete | OperationKind::Check, Some(_)) => ApiError::PreconditionFailed,
+        (OperationKind::Delete | OperationKind::Check, None) => ApiError::NotFound,
+    }
+}
+
+async fn replace_one(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ItemKey(key): ItemKey,
+    Json(mut item): Json<Item>,
+) -> Result<Response, ApiError> {
+    let pk = &state.config.pk;
+    let if_match = parse_if_match(&headers)?;
+
+    // Work out the version being replaced so the write can be made
+    // conditional on it, reading the item only when the client did not say.
+    // `None` means there is no item yet, `Some(None)` one that predates
+    // versioning.
+    let current = match if_match {
+        _ if if_none_match_any(&headers) => None,
+        Some(IfMatch::Version(version)) => Some(Some(version)),
+        _ => match state.store.get(&key, None).await? {
+            Some(current) => Some(version(&current)),
+            None if if_match.is_some() => return Err(ApiError::PreconditionFailed),
+            None => None,
+        },
     };
+    let condition = unchanged(pk, current);
+    let next_version = current.flatten().unwrap_or_default() + 1;
+
+    item.extend(state.config.keys().attributes(&key));
+    item.insert(VERSION.to_string(), Value::from(next_version));
+    item.insert(UPDATED_AT.to_string(), now());
+
+    match state.store.put(item.clone(), Some(condition)).await {
+        Err(StoreError::ConditionFailed) if if_match.is_none() && current.is_some() => {
+            return Err(ApiError::Conflict("item was modified concurrently".into()))
+        }
+        Err(StoreError::ConditionFailed) => return Err(ApiError::PreconditionFailed),
+        result => result?,
+    }
+
+    let validators = AppendHeaders(validators(&item));
+    if current.is_none() {
+        let location = [(LOCATION, format!("/items/{}", key.path()))];
+        return Ok((StatusCode::CREATED, location, validators, Json(item)).into_response());
+    }
+
+    Ok((validators, Json(item)).into_response())
+}
+
+/// A failed `If-Match` condition means the client's copy is stale.
+fn precondition_failed(e: StoreError) -> ApiError {
+    match e {
+        StoreError::ConditionFailed => ApiError::PreconditionFailed,
+        e => e.into(),
+    }
+}
+
+/// Responds with what a write returned, if anything. Only the item as it is
+/// after the write carries validators.
+fn returned(item: Option<Item>, returns: ReturnValues) -> Response {
+    match item {
+        Some(item) if returns == ReturnValues::AllOld => Json(item).into_response(),
+        Some(item) => (AppendHeaders(validators(&item)), Json(item)).into_response(),
+        None => StatusCode::OK.into_response(),
+    }
+}
 
-    let update_expression = format!("{}{}", update_expression, remove_expression);
+#[derive(Debug, Default, Deserialize)]
+struct DeleteParams {
+    /// Only `none` and `all_old` apply to a delete.
+    #[serde(default, rename = "return")]
+    returns: ReturnValues,
+}
 
-    if update_expression.is_empty() {
-        return Ok(());
+async fn delete_one(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ItemKey(key): ItemKey,
+    Query(params): Query<DeleteParams>,
+) -> Result<Response, ApiError> {
+    if !matches!(params.returns, ReturnValues::None | ReturnValues::AllOld) {
+        return Err(ApiError::BadRequest(
+            "DELETE only supports return=none or return=all_old".into(),
+        ));
     }
+    let condition = if_match(&headers, &state.config.pk)?;
 
-    let _ = client
-        .update_item()
-        .table_name(TABLE_NAME.to_string())
-        .key(PK.to_string(), AttributeValue::S(id))
-        .update_expression(update_expression)
-        .set_expression_attribute_names(Some(expression_attribute_name))
-        .set_expression_attribute_values(Some(expression_attribute_value))
-        .send()
+    let old = state
+        .store
+        .delete(&key, condition, params.returns)
         .await
-        .map_err(|e| {
-            tracing::error!("error: {:?}", e);
-            StatusCode::INTERNAL_SERVER_ERROR
-        })?;
+        .map_err(precondition_failed)?;
+
+    Ok(returned(old, params.returns))
+}
 
-    Ok(())
+/// Works out why a PATCH failed. A failed condition means the item does not
+/// exist, the client's `If-Match` is stale, that the item changed after a
+/// patch that `reads_current` read it, or otherwise that the patch does not
+/// apply to the item as stored.
+async fn update_failed(
+    state: &AppState,
+    key: &Key,
+    e: StoreError,
+    if_match: Option<IfMatch>,
+    reads_current: bool,
+) -> ApiError {
+    match e {
+        StoreError::ConditionFailed => {}
+        StoreError::Validation(detail) => return ApiError::Validation(detail),
+        e => return e.into(),
+    }
+
+    match state.store.get(key, None).await {
+        Ok(None) => ApiError::NotFound,
+        Ok(Some(item)) if if_match.is_some_and(|if_match| !if_match.matches(&item)) => {
+            ApiError::PreconditionFailed
+        }
+        Ok(Some(_)) if reads_current => {
+            ApiError::Conflict("item changed while the patch was applied".into())
+        }
+        Ok(Some(_)) => ApiError::Conflict("patch does not apply to the current item".into()),
+        Err(e) => e.into(),
+    }
+}
+
+/// Most times a PATCH that reads the item is applied before giving up on
+/// other writes changing the item in between.
+const PATCH_ATTEMPTS: usize = 5;
+
+#[derive(Debug, Deserialize)]
+struct UpdateParams {
+    /// Create the item if it does not exist instead of answering 404.
+    #[serde(default)]
+    upsert: bool,
+    #[serde(default = "updated_new", rename = "return")]
+    returns: ReturnValues,
+}
+
+fn updated_new() -> ReturnValues {
+    ReturnValues::UpdatedNew
+}
+
+/// Compiles a PATCH body into an update plus the conditions the stored item
+/// must meet, reading the item first if the patch depends on it.
+async fn compile_patch(
+    state: &AppState,
+    key: &Key,
+    body: PatchBody,
+    upsert: bool,
+    if_match: Option<IfMatch>,
+) -> Result<(Update, Vec<Condition>), ApiError> {
+    let pk = &state.config.pk;
+
+    let (update, mut conditions) = match body {
+        PatchBody::Merge(mut patch) => {
+            // Merging into nested objects and changing sets depend on what is
+            // stored, so read the item and only write if it is still
+            // unchanged.
+            if reads_current(&patch) {
+                let current = match state.store.get(key, None).await? {
+                    None if !upsert => return Err(ApiError::NotFound),
+                    current => current,
+                };
+                let condition = unchanged(pk, current.as_ref().map(version));
+                let operators = operators(&mut patch, current.as_ref())?;
+                let mut update = merge_patch(patch, current.as_ref());
+                update.extend(operators);
+                (update, vec![condition])
+            } else {
+                let exists = (!upsert).then(|| Condition::Exists(DocumentPath::attr(pk)));
+                let operators = operators(&mut patch, None)?;
+                let mut update = merge_patch(patch, None);
+                update.extend(operators);
+                (update, exists.into_iter().collect())
+            }
+        }
+        PatchBody::Json(operations) => {
+            let (update, mut conditions) = json_patch(operations)?;
+            conditions.push(Condition::Exists(DocumentPath::attr(pk)));
+            (update, conditions)
+        }
+    };
+
+    // The key identifies the item, so it can only change by replacing it.
+    let keys = state.config.keys();
+    if let Some(path) = update
+        .paths()
+        .find(|path| keys.is_key(path.top()) || [VERSION, UPDATED_AT].contains(&path.top()))
+    {
+        return Err(ApiError::Validation(format!(
+            "{} cannot be modified",
+            path.top()
+        )));
+    }
+    conditions.extend(if_match.map(|if_match| if_match.condition(pk)));
+
+    Ok((update, conditions))
+}
+
+/// Moves the item on to its next version as part of `update`.
+fn next_version(update: &mut Update) {
+    update.add.insert(VERSION.to_string(), Value::from(1));
+    update
+        .set
+        .push((DocumentPath::attr(UPDATED_AT), Operand::Value(now())));
+}
+
+async fn update_one(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    ItemKey(key): ItemKey,
+    Query(params): Query<UpdateParams>,
+    body: PatchBody,
+) -> Result<Response, ApiError> {
+    let if_match = parse_if_match(&headers)?;
+    let reads_current = body.reads_current();
+
+    // A patch that reads the item is only written if the item is unchanged
+    // by then, so it is applied again to whatever another write left. With
+    // `If-Match`, such a change means the client's version is stale instead.
+    let mut attempts = if reads_current && if_match.is_none() {
+        PATCH_ATTEMPTS
+    } else {
+        1
+    };
+    loop {
+        attempts -= 1;
+        let (mut update, conditions) =
+            compile_patch(&state, &key, body.clone(), params.upsert, if_match).await?;
+
+        // A patch made only of tests changes nothing, so check it against the
+        // stored item without writing.
+        if update.is_empty() {
+            let current = state.store.get(&key, None).await?;
+            return match Condition::all(conditions) {
+                Some(condition) if !condition.matches(current.as_ref()) => {
+                    let e = StoreError::ConditionFailed;
+                    Err(update_failed(&state, &key, e, if_match, reads_current).await)
+                }
+                _ => Ok(returned(
+                    match params.returns {
+                        ReturnValues::None => None,
+                        ReturnValues::UpdatedNew => Some(Item::new()),
+                        ReturnValues::AllNew | ReturnValues::AllOld => current,
+                    },
+                    params.returns,
+                )),
+            };
+        }
+        next_version(&mut update);
+
+        match state
+            .store
+            .update(&key, update, Condition::all(conditions), params.returns)
+            .await
+        {
+            Err(StoreError::ConditionFailed) if attempts > 0 => continue,
+            Err(e) => return Err(update_failed(&state, &key, e, if_match, reads_current).await),
+            Ok(item) => return Ok(returned(item, params.returns)),
+        }
+    }
 }
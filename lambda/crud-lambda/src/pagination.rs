@@ -0,0 +1,76 @@
+use std::num::NonZeroUsize;
+
+use axum::http::Uri;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+#[derive(Debug, Deserialize)]
+pub struct PageParams {
+    pub limit: Option<NonZeroUsize>,
+    pub cursor: Option<String>,
+}
+
+impl PageParams {
+    /// Query parameters taken by paging rather than filtering.
+    pub const NAMES: &'static [&'static str] = &["limit", "cursor"];
+}
+
+/// Cursors are the scan's `LastEvaluatedKey` as base64url-encoded JSON, so they
+/// round-trip between requests without the client having to understand them.
+/// Parallel scans keep the progress of each segment in theirs instead.
+pub fn encode_cursor<T: Serialize>(key: &T) -> String {
+    URL_SAFE_NO_PAD.encode(serde_json::to_vec(key).expect("key is serializable"))
+}
+
+pub fn decode_cursor<T: DeserializeOwned>(cursor: &str) -> Option<T> {
+    let bytes = URL_SAFE_NO_PAD.decode(cursor).ok()?;
+    serde_json::from_slice(&bytes).ok()
+}
+
+/// Builds a `Link` header value pointing at the page after `last_key`, keeping
+/// every other query parameter of the current request.
+pub fn next_link<T: Serialize>(uri: &Uri, last_key: &T) -> String {
+    let mut query: Vec<(String, String)> = uri
+        .query()
+        .and_then(|query| serde_urlencoded::from_str(query).ok())
+        .unwrap_or_default();
+    query.retain(|(k, _)| k != "cursor");
+    query.push(("cursor".into(), encode_cursor(last_key)));
+
+    format!(
+        "<{}?{}>; rel=\"next\"",
+        uri.path(),
+        serde_urlencoded::to_string(query).expect("query is serializable")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    #[test]
+    fn cursors_round_trip() {
+        let key = json!({"itemId": "a/b?c"});
+        let cursor = encode_cursor(&key);
+        assert!(cursor
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_'));
+        assert_eq!(decode_cursor::<Value>(&cursor), Some(key));
+        assert_eq!(decode_cursor::<Value>("not a cursor"), None);
+    }
+
+    #[test]
+    fn next_link_replaces_the_cursor_and_keeps_other_parameters() {
+        let uri: Uri = "/items?limit=2&cursor=old&status=open".parse().unwrap();
+        let link = next_link(&uri, &json!({"itemId": "a"}));
+        assert_eq!(
+            link,
+            format!(
+                "</items?limit=2&status=open&cursor={}>; rel=\"next\"",
+                encode_cursor(&json!({"itemId": "a"}))
+            )
+        );
+    }
+}
@@ -0,0 +1,565 @@
+use axum::{
+    async_trait,
+    extract::{FromRequest, Request},
+    http::header::CONTENT_TYPE,
+};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+    error::ApiError,
+    extract::Json,
+    store::{Condition, DocumentPath, Item, Operand, Segment, Update},
+};
+
+/// Whether applying `patch` depends on what is currently stored, which is the
+/// case as soon as it merges into an object or changes a set.
+pub fn reads_current(patch: &Item) -> bool {
+    patch.iter().any(|(k, v)| match k.as_str() {
+        "$addToSet" | "$removeFromSet" => true,
+        k if k.starts_with('$') => false,
+        _ => v.is_object(),
+    })
+}
+
+/// Compiles a JSON Merge Patch (RFC 7396) into an `Update`. Objects in the
+/// patch are merged member by member into objects already at that path in
+/// `current`, and assigned whole (minus their nulls) anywhere else.
+pub fn merge_patch(patch: Item, current: Option<&Item>) -> Update {
+    let mut update = Update::default();
+    for (k, v) in patch {
+        merge(DocumentPath::attr(k), v, current, &mut update);
+    }
+    update
+}
+
+fn merge(path: DocumentPath, value: Value, current: Option<&Item>, update: &mut Update) {
+    match value {
+        Value::Null => update.remove.push(path),
+        Value::Object(members)
+            if current
+                .and_then(|current| path.get(current))
+                .is_some_and(Value::is_object) =>
+        {
+            for (k, v) in members {
+                merge(path.child(k), v, current, update);
+            }
+        }
+        Value::Object(members) => update
+            .set
+            .push((path, Operand::Value(Value::Object(strip_nulls(members))))),
+        value => update.set.push((path, Operand::Value(value))),
+    }
+}
+
+fn strip_nulls(members: Map<String, Value>) -> Map<String, Value> {
+    members
+        .into_iter()
+        .filter(|(_, v)| !v.is_null())
+        .map(|(k, v)| match v {
+            Value::Object(members) => (k, Value::Object(strip_nulls(members))),
+            v => (k, v),
+        })
+        .collect()
+}
+
+/// Takes the update operators (`$inc`, `$append`, `$addToSet` and
+/// `$removeFromSet`) out of a merge patch and compiles them into an `Update`.
+/// Each maps top-level attribute names to its operand. `$inc` and `$append`
+/// apply atomically, without reading the item first.
+///
+/// Sets are stored as lists, like any other array, so `$addToSet` and
+/// `$removeFromSet` work out the new list from the item as `current` holds
+/// it, which the write must then be conditional on.
+pub fn operators(patch: &mut Item, current: Option<&Item>) -> Result<Update, ApiError> {
+    let mut update = Update::default();
+    let operators: Vec<_> = patch
+        .keys()
+        .filter(|k| k.starts_with('$'))
+        .cloned()
+        .collect();
+
+    for operator in operators {
+        let Some(Value::Object(operands)) = patch.remove(&operator) else {
+            return Err(ApiError::Validation(format!(
+                "{} must map attribute names to operands",
+                operator
+            )));
+        };
+        for (k, v) in operands {
+            match operator.as_str() {
+                "$inc" if v.is_number() => {
+                    update.add.insert(k, v);
+                }
+                "$inc" => {
+                    return Err(ApiError::Validation(format!(
+                        "$inc of {} must be a number",
+                        k
+                    )))
+                }
+                "$append" => update.set.push((
+                    DocumentPath::attr(&k),
+                    Operand::AppendOrCreate(DocumentPath::attr(k), elements(v)),
+                )),
+                "$addToSet" => {
+                    let mut list = list(current, &k, &operator)?;
+                    for element in set(&k, v)? {
+                        if !list.contains(&element) {
+                            list.push(element);
+                        }
+                    }
+                    update
+                        .set
+                        .push((DocumentPath::attr(k), Operand::Value(Value::Array(list))));
+                }
+                "$removeFromSet" => {
+                    let mut list = list(current, &k, &operator)?;
+                    let elements = set(&k, v)?;
+                    list.retain(|element| !elements.contains(element));
+                    update
+                        .set
+                        .push((DocumentPath::attr(k), Operand::Value(Value::Array(list))));
+                }
+                _ => {
+                    return Err(ApiError::Validation(format!(
+                        "unknown update operator {}",
+                        operator
+                    )))
+                }
+            }
+        }
+    }
+
+    Ok(update)
+}
+
+/// Operands may be a single element or an array of them.
+fn elements(value: Value) -> Vec<Value> {
+    match value {
+        Value::Array(elements) => elements,
+        element => vec![element],
+    }
+}
+
+/// Set elements must be all strings or all numbers, and there must be some.
+fn set(attr: &str, value: Value) -> Result<Vec<Value>, ApiError> {
+    let elements = elements(value);
+    let is_valid = !elements.is_empty()
+        && (elements.iter().all(Value::is_string) || elements.iter().all(Value::is_number));
+    match is_valid {
+        true => Ok(elements),
+        false => Err(ApiError::Validation(format!(
+            "set elements of {} must be all strings or all numbers",
+            attr
+        ))),
+    }
+}
+
+/// The list stored at `attr`, which is empty if there is none yet.
+fn list(current: Option<&Item>, attr: &str, operator: &str) -> Result<Vec<Value>, ApiError> {
+    match current.and_then(|current| current.get(attr)) {
+        None => Ok(vec![]),
+        Some(Value::Array(list)) => Ok(list.clone()),
+        Some(_) => Err(ApiError::Validation(format!(
+            "{} of {} needs a list",
+            operator, attr
+        ))),
+    }
+}
+
+const JSON_PATCH: &str = "application/json-patch+json";
+
+/// A PATCH request body, told apart by its content type. Embedded in other
+/// bodies, an object is a merge patch and an array a JSON Patch.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum PatchBody {
+    Merge(Item),
+    Json(Vec<Operation>),
+}
+
+impl PatchBody {
+    /// Whether applying the patch depends on what is currently stored.
+    pub fn reads_current(&self) -> bool {
+        matches!(self, PatchBody::Merge(patch) if reads_current(patch))
+    }
+}
+
+#[async_trait]
+impl<S: Send + Sync> FromRequest<S> for PatchBody {
+    type Rejection = ApiError;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let is_json_patch = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with(JSON_PATCH));
+
+        if is_json_patch {
+            let Json(operations) = Json::from_request(req, state).await?;
+            Ok(PatchBody::Json(operations))
+        } else {
+            let Json(patch) = Json::from_request(req, state).await?;
+            Ok(PatchBody::Merge(patch))
+        }
+    }
+}
+
+/// One JSON Patch (RFC 6902) operation.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Add { path: String, value: Value },
+    Remove { path: String },
+    Replace { path: String, value: Value },
+    Move { from: String, path: String },
+    Copy { from: String, path: String },
+    Test { path: String, value: Value },
+}
+
+/// Target of a JSON Pointer: a document path, or the end of a list for `-`.
+enum Target {
+    Path(DocumentPath),
+    End(DocumentPath),
+}
+
+/// Parses a JSON Pointer (RFC 6901). Tokens made of digits address list
+/// elements, except for the first, which always names a top-level attribute.
+fn pointer(pointer: &str) -> Result<Target, ApiError> {
+    let invalid = || ApiError::Validation(format!("invalid JSON pointer {:?}", pointer));
+
+    let mut tokens = pointer
+        .strip_prefix('/')
+        .ok_or_else(invalid)?
+        .split('/')
+        .map(|token| token.replace("~1", "/").replace("~0", "~"))
+        .peekable();
+    let mut path = DocumentPath::attr(tokens.next().ok_or_else(invalid)?);
+
+    while let Some(token) = tokens.next() {
+        if token == "-" {
+            return match tokens.peek() {
+                None => Ok(Target::End(path)),
+                Some(_) => Err(invalid()),
+            };
+        }
+        let is_index = !token.is_empty()
+            && token.bytes().all(|b| b.is_ascii_digit())
+            && (token == "0" || !token.starts_with('0'));
+        path = match is_index {
+            true => path.index(token.parse().map_err(|_| invalid())?),
+            false => path.child(token),
+        };
+    }
+
+    Ok(Target::Path(path))
+}
+
+fn path(pointer_str: &str) -> Result<DocumentPath, ApiError> {
+    match pointer(pointer_str)? {
+        Target::Path(path) => Ok(path),
+        Target::End(_) => Err(ApiError::Validation(format!(
+            "{:?} may only be used to add to a list",
+            pointer_str
+        ))),
+    }
+}
+
+/// Compiles JSON Patch operations into a single `Update` plus the conditions
+/// the stored item must meet: `test` operations, and the existence of every
+/// location that is read, replaced or removed.
+///
+/// DynamoDB applies the whole update at once, so operations cannot build on
+/// each other and may not touch overlapping paths. Inserting into the middle
+/// of a list has no DynamoDB equivalent and is rejected.
+pub fn json_patch(operations: Vec<Operation>) -> Result<(Update, Vec<Condition>), ApiError> {
+    let mut update = Update::default();
+    let mut conditions = vec![];
+
+    for operation in operations {
+        match operation {
+            Operation::Add { path, value } => match pointer(&path)? {
+                Target::End(list) => update
+                    .set
+                    .push((list.clone(), Operand::Append(list, vec![value]))),
+                Target::Path(path) => {
+                    if let Some(Segment::Index(_)) = path.segments().last() {
+                        return Err(ApiError::Validation(
+                            "adding at a list index is not supported, append with \"-\" or use replace"
+                                .into(),
+                        ));
+                    }
+                    update.set.push((path, Operand::Value(value)));
+                }
+            },
+            Operation::Remove { path } => {
+                let path = self::path(&path)?;
+                conditions.push(Condition::Exists(path.clone()));
+                update.remove.push(path);
+            }
+            Operation::Replace { path, value } => {
+                let path = self::path(&path)?;
+                conditions.push(Condition::Exists(path.clone()));
+                update.set.push((path, Operand::Value(value)));
+            }
+            Operation::Move { from, path } => {
+                let (from, path) = (self::path(&from)?, self::path(&path)?);
+                if from == path {
+                    continue;
+                }
+                conditions.push(Condition::Exists(from.clone()));
+                update.set.push((path, Operand::Path(from.clone())));
+                update.remove.push(from);
+            }
+            Operation::Copy { from, path } => {
+                let (from, path) = (self::path(&from)?, self::path(&path)?);
+                conditions.push(Condition::Exists(from.clone()));
+                update.set.push((path, Operand::Path(from)));
+            }
+            Operation::Test { path, value } => {
+                conditions.push(Condition::Equals(self::path(&path)?, value));
+            }
+        }
+    }
+
+    if update.has_overlapping_paths() {
+        return Err(ApiError::Validation(
+            "operations must not touch overlapping paths".into(),
+        ));
+    }
+
+    Ok((update, conditions))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn item(value: Value) -> Item {
+        serde_json::from_value(value).unwrap()
+    }
+
+    fn set(update: &Update) -> Vec<(DocumentPath, Value)> {
+        update
+            .set
+            .iter()
+            .map(|(path, operand)| match operand {
+                Operand::Value(value) => (path.clone(), value.clone()),
+                operand => panic!("unexpected operand {:?}", operand),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merges_into_stored_objects() {
+        let current = item(json!({"meta": {"a": 1, "b": 2}, "x": 1}));
+        let patch = item(json!({
+            "meta": {"a": null, "c": 3},
+            "x": null,
+            "y": {"z": null, "w": {"v": null}},
+        }));
+
+        let update = merge_patch(patch, Some(&current));
+        assert_eq!(
+            set(&update),
+            [
+                (DocumentPath::attr("meta").child("c"), json!(3)),
+                (DocumentPath::attr("y"), json!({"w": {}})),
+            ]
+        );
+        assert_eq!(
+            update.remove,
+            [
+                DocumentPath::attr("meta").child("a"),
+                DocumentPath::attr("x"),
+            ]
+        );
+    }
+
+    #[test]
+    fn assigns_objects_whole_without_a_stored_object() {
+        let patch = item(json!({"meta": {"a": 1}}));
+
+        let update = merge_patch(patch.clone(), None);
+        assert_eq!(
+            set(&update),
+            [(DocumentPath::attr("meta"), json!({"a": 1}))]
+        );
+
+        let current = item(json!({"meta": "not an object"}));
+        let update = merge_patch(patch, Some(&current));
+        assert_eq!(
+            set(&update),
+            [(DocumentPath::attr("meta"), json!({"a": 1}))]
+        );
+        assert!(reads_current(&item(json!({"meta": {}}))));
+        assert!(!reads_current(&item(json!({"meta": [{}]}))));
+    }
+
+    fn operations(value: Value) -> Vec<Operation> {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn compiles_json_patch_operations() {
+        let (update, conditions) = json_patch(operations(json!([
+            {"op": "test", "path": "/v", "value": 1},
+            {"op": "add", "path": "/tags/-", "value": "new"},
+            {"op": "replace", "path": "/meta/a~1b", "value": 2},
+            {"op": "remove", "path": "/list/0"},
+            {"op": "move", "from": "/old", "path": "/new"},
+            {"op": "copy", "from": "/src", "path": "/dst"},
+        ])))
+        .unwrap();
+
+        let tags = DocumentPath::attr("tags");
+        let meta = DocumentPath::attr("meta").child("a/b");
+        assert_eq!(update.set.len(), 4);
+        let (path, Operand::Append(list, elements)) = &update.set[0] else {
+            panic!("expected an append, got {:?}", update.set[0]);
+        };
+        assert_eq!((path, list, elements), (&tags, &tags, &vec![json!("new")]));
+        let (path, Operand::Value(value)) = &update.set[1] else {
+            panic!("expected a value, got {:?}", update.set[1]);
+        };
+        assert_eq!((path, value), (&meta, &json!(2)));
+        let (path, Operand::Path(from)) = &update.set[2] else {
+            panic!("expected a copy, got {:?}", update.set[2]);
+        };
+        assert_eq!(
+            (path, from),
+            (&DocumentPath::attr("new"), &DocumentPath::attr("old"))
+        );
+        assert_eq!(
+            update.remove,
+            [
+                DocumentPath::attr("list").index(0),
+                DocumentPath::attr("old")
+            ]
+        );
+
+        let Condition::Equals(path, value) = &conditions[0] else {
+            panic!("expected a test, got {:?}", conditions[0]);
+        };
+        assert_eq!((path, value), (&DocumentPath::attr("v"), &json!(1)));
+        let exists: Vec<_> = conditions[1..]
+            .iter()
+            .map(|condition| match condition {
+                Condition::Exists(path) => path.clone(),
+                condition => panic!("unexpected condition {:?}", condition),
+            })
+            .collect();
+        assert_eq!(
+            exists,
+            [
+                meta,
+                DocumentPath::attr("list").index(0),
+                DocumentPath::attr("old"),
+                DocumentPath::attr("src"),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_json_patches_dynamodb_cannot_apply() {
+        for patch in [
+            json!([{"op": "add", "path": "/list/0", "value": 1}]),
+            json!([{"op": "remove", "path": "/list/-"}]),
+            json!([{"op": "replace", "path": "no/slash", "value": 1}]),
+            json!([{"op": "add", "path": "/list/-/x", "value": 1}]),
+            json!([
+                {"op": "replace", "path": "/a", "value": 1},
+                {"op": "remove", "path": "/a/b"},
+            ]),
+        ] {
+            assert!(
+                matches!(
+                    json_patch(operations(patch.clone())),
+                    Err(ApiError::Validation(_))
+                ),
+                "{}",
+                patch
+            );
+        }
+    }
+
+    #[test]
+    fn moving_a_value_onto_itself_does_nothing() {
+        let (update, conditions) = json_patch(operations(
+            json!([{"op": "move", "from": "/a", "path": "/a"}]),
+        ))
+        .unwrap();
+        assert!(update.is_empty());
+        assert!(conditions.is_empty());
+    }
+
+    #[test]
+    fn compiles_update_operators() {
+        let mut patch = item(json!({
+            "$inc": {"stock": -1},
+            "$append": {"log": "sold"},
+            "name": "kept",
+        }));
+
+        let update = operators(&mut patch, None).unwrap();
+        assert_eq!(Value::Object(update.add), json!({"stock": -1}));
+        let (path, Operand::AppendOrCreate(list, elements)) = &update.set[0] else {
+            panic!("expected an append, got {:?}", update.set[0]);
+        };
+        let log = DocumentPath::attr("log");
+        assert_eq!((path, list, elements), (&log, &log, &vec![json!("sold")]));
+        assert_eq!(Value::Object(patch), json!({"name": "kept"}));
+    }
+
+    #[test]
+    fn changes_sets_as_lists() {
+        let current = item(json!({"tags": ["a", "b"], "ids": [1, 2, 3], "name": "x"}));
+        let mut patch = item(json!({
+            "$addToSet": {"tags": ["b", "c", "c"], "new": "x"},
+            "$removeFromSet": {"ids": [2, 9]},
+        }));
+
+        let update = operators(&mut patch, Some(&current)).unwrap();
+        let mut set = set(&update);
+        set.sort_by(|(a, _), (b, _)| a.top().cmp(b.top()));
+        assert_eq!(
+            set,
+            [
+                (DocumentPath::attr("ids"), json!([1, 3])),
+                (DocumentPath::attr("new"), json!(["x"])),
+                (DocumentPath::attr("tags"), json!(["a", "b", "c"])),
+            ]
+        );
+        assert!(reads_current(&item(json!({"$addToSet": {"tags": "a"}}))));
+        assert!(!reads_current(&item(json!({"$inc": {"n": 1}}))));
+
+        let mut patch = item(json!({"$addToSet": {"name": "y"}}));
+        assert!(matches!(
+            operators(&mut patch, Some(&current)),
+            Err(ApiError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_operands() {
+        for patch in [
+            json!({"$inc": {"n": "1"}}),
+            json!({"$inc": 1}),
+            json!({"$addToSet": {"tags": []}}),
+            json!({"$addToSet": {"tags": ["a", 1]}}),
+            json!({"$unknown": {"n": 1}}),
+        ] {
+            assert!(
+                matches!(
+                    operators(&mut item(patch.clone()), None),
+                    Err(ApiError::Validation(_))
+                ),
+                "{}",
+                patch
+            );
+        }
+    }
+}
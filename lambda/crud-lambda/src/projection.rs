@@ -0,0 +1,100 @@
+use serde::Deserialize;
+
+use crate::{
+    error::ApiError,
+    store::{DocumentPath, KeySchema},
+};
+
+#[derive(Debug, Deserialize)]
+pub struct FieldsParams {
+    pub fields: Option<String>,
+}
+
+impl FieldsParams {
+    /// Query parameters taken by projection rather than filtering.
+    pub const NAMES: &'static [&'static str] = &["fields"];
+
+    /// The paths listed in `?fields=name,meta.color,tags[0]`, always including
+    /// the key attributes so items can still be told apart.
+    pub fn projection(&self, keys: &KeySchema) -> Result<Option<Vec<DocumentPath>>, ApiError> {
+        let Some(fields) = &self.fields else {
+            return Ok(None);
+        };
+
+        let mut paths: Vec<_> = [Some(&keys.pk), keys.sk.as_ref()]
+            .into_iter()
+            .flatten()
+            .map(DocumentPath::attr)
+            .collect();
+        for field in fields.split(',').map(str::trim) {
+            let path = DocumentPath::parse(field)
+                .ok_or_else(|| ApiError::BadRequest(format!("invalid field {:?}", field)))?;
+            include(&mut paths, path);
+        }
+        Ok(Some(paths))
+    }
+}
+
+/// Adds `path` to a projection unless it is already covered. DynamoDB rejects
+/// overlapping paths, so paths inside `path` make way for it.
+pub fn include(paths: &mut Vec<DocumentPath>, path: DocumentPath) {
+    let covered = paths
+        .iter()
+        .any(|other| other.overlaps(&path) && other.segments().len() <= path.segments().len());
+    if !covered {
+        paths.retain(|other| !other.overlaps(&path));
+        paths.push(path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn projection(fields: &str, sk: Option<&str>) -> Result<Option<Vec<DocumentPath>>, ApiError> {
+        let keys = KeySchema {
+            pk: "id".into(),
+            sk: sk.map(String::from),
+        };
+        FieldsParams {
+            fields: Some(fields.into()),
+        }
+        .projection(&keys)
+    }
+
+    #[test]
+    fn always_projects_the_keys() {
+        let parse = |path| DocumentPath::parse(path).unwrap();
+        assert_eq!(
+            projection("name, meta.color", Some("sk")).unwrap(),
+            Some(vec![
+                parse("id"),
+                parse("sk"),
+                parse("name"),
+                parse("meta.color")
+            ])
+        );
+        let none = FieldsParams { fields: None };
+        assert_eq!(
+            none.projection(&KeySchema {
+                pk: "id".into(),
+                sk: None
+            })
+            .unwrap(),
+            None
+        );
+        assert!(matches!(
+            projection("name,,x", None),
+            Err(ApiError::BadRequest(_))
+        ));
+    }
+
+    #[test]
+    fn merges_overlapping_paths() {
+        let parse = |path| DocumentPath::parse(path).unwrap();
+        assert_eq!(
+            projection("meta.color,meta,meta.size,id.x", None).unwrap(),
+            Some(vec![parse("id"), parse("meta")])
+        );
+    }
+}
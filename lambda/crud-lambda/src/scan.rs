@@ -0,0 +1,268 @@
+use std::{
+    num::NonZeroU32,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use lambda_http::Context;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{mpsc, Semaphore},
+    time::Instant,
+};
+
+use crate::{
+    config::Config,
+    store::{Item, ItemStore, PageRequest, ScanSegment, StoreError},
+};
+
+/// Most segments a parallel scan may be split into.
+pub const MAX_SEGMENTS: u32 = 1000;
+
+/// Most bytes of items a parallel scan hands back at once, well below the 6 MB
+/// Lambda allows a buffered response.
+pub const MAX_RESPONSE_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct ScanParams {
+    /// Scan the table in this many segments at once.
+    pub segments: Option<NonZeroU32>,
+}
+
+impl ScanParams {
+    /// Query parameters taken by parallel scans rather than filtering.
+    pub const NAMES: &'static [&'static str] = &["segments"];
+}
+
+/// How far a parallel scan got: the segments it has yet to finish, with the
+/// key to resume each after once some of it was read.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScanProgress {
+    pub total: u32,
+    pub pending: Vec<PendingSegment>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingSegment {
+    pub segment: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub start_key: Option<Item>,
+}
+
+impl ScanProgress {
+    /// A scan of `total` segments that has not started.
+    pub fn new(total: u32) -> Self {
+        Self {
+            total,
+            pending: (0..total)
+                .map(|segment| PendingSegment {
+                    segment,
+                    start_key: None,
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether the segments are the ones of a scan of `total` segments.
+    pub fn fits(&self, total: u32) -> bool {
+        self.total == total && self.pending.iter().all(|pending| pending.segment < total)
+    }
+
+    /// The first `n` pending segments, as a scan of their own.
+    pub fn take(&self, n: usize) -> ScanProgress {
+        Self {
+            total: self.total,
+            pending: self.pending.iter().take(n).cloned().collect(),
+        }
+    }
+
+    /// Moves the segment of `page` on past it, or off the pending segments if
+    /// it was the last.
+    pub fn advance(&mut self, page: &SegmentPage) {
+        let Some(i) = self
+            .pending
+            .iter()
+            .position(|pending| pending.segment == page.segment)
+        else {
+            return;
+        };
+        match &page.last_key {
+            Some(last_key) => self.pending[i].start_key = Some(last_key.clone()),
+            None => {
+                self.pending.remove(i);
+            }
+        }
+    }
+}
+
+/// A page read by one segment of a parallel scan, with the key to resume the
+/// segment after if it has more.
+#[derive(Debug)]
+pub struct SegmentPage {
+    pub segment: u32,
+    pub items: Vec<Item>,
+    pub last_key: Option<Item>,
+}
+
+/// How long the Lambda invocation has left, less the time kept back for
+/// finishing the response; `None` outside Lambda.
+fn remaining(config: &Config, context: Option<&Context>) -> Option<Duration> {
+    let remaining = context?
+        .deadline()
+        .duration_since(SystemTime::now())
+        .unwrap_or(Duration::ZERO);
+    Some(remaining.saturating_sub(config.scan_time_margin))
+}
+
+/// When a parallel scan must stop reading: once its time budget is spent, or
+/// in Lambda early enough to still send the response before the invocation
+/// times out.
+pub fn scan_deadline(config: &Config, context: Option<&Context>) -> Instant {
+    let budget = match remaining(config, context) {
+        Some(remaining) => config.scan_time_budget.min(remaining),
+        None => config.scan_time_budget,
+    };
+    Instant::now() + budget
+}
+
+/// When an export must stop reading. Exports are not held to the time budget
+/// of a single page of results, only to the Lambda deadline, if any.
+pub fn export_deadline(config: &Config, context: Option<&Context>) -> Option<Instant> {
+    remaining(config, context).map(|remaining| Instant::now() + remaining)
+}
+
+/// Reads the pending segments of `progress` in their own tasks, at most
+/// `concurrency` at a time, each one page of `request` after another, up to
+/// `pages_per_segment` pages if given. Pages
+/// arrive on the returned channel as the segments read them, in no particular
+/// order; passing each page taken off it to `ScanProgress::advance` keeps
+/// track of where to resume. A segment stops between pages once a `deadline`
+/// has passed, and dropping the channel stops the scan, leaving any pages not
+/// taken to be read again on resuming.
+pub fn parallel_scan(
+    store: Arc<dyn ItemStore>,
+    progress: ScanProgress,
+    request: PageRequest,
+    concurrency: usize,
+    deadline: Option<Instant>,
+    pages_per_segment: Option<usize>,
+) -> mpsc::Receiver<Result<SegmentPage, StoreError>> {
+    let (sender, pages) = mpsc::channel(concurrency);
+    let permits = Arc::new(Semaphore::new(concurrency));
+    let total = progress.total;
+
+    for pending in progress.pending {
+        let (store, sender, permits) = (store.clone(), sender.clone(), permits.clone());
+        let request = request.clone();
+        tokio::spawn(async move {
+            let Ok(_permit) = permits.acquire_owned().await else {
+                return;
+            };
+            let segment = ScanSegment {
+                segment: pending.segment,
+                total,
+            };
+            let mut start_key = pending.start_key;
+            for _ in 0..pages_per_segment.unwrap_or(usize::MAX) {
+                if sender.is_closed() || deadline.is_some_and(|deadline| Instant::now() >= deadline)
+                {
+                    return;
+                }
+                let request = PageRequest {
+                    start_key: start_key.take(),
+                    segment: Some(segment),
+                    ..request.clone()
+                };
+                // Errors end the whole scan, and a closed channel means
+                // nobody is reading it any more.
+                let page = match store.scan(request).await {
+                    Ok(page) => page,
+                    Err(e) => {
+                        let _ = sender.send(Err(e)).await;
+                        return;
+                    }
+                };
+                let page = SegmentPage {
+                    segment: segment.segment,
+                    items: page.items,
+                    last_key: page.last_key,
+                };
+                start_key = page.last_key.clone();
+                if sender.send(Ok(page)).await.is_err() || start_key.is_none() {
+                    return;
+                }
+            }
+        });
+    }
+
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::store::{KeySchema, MemoryStore};
+
+    fn page(segment: u32, last_key: Option<Item>) -> SegmentPage {
+        SegmentPage {
+            segment,
+            items: vec![],
+            last_key,
+        }
+    }
+
+    fn key(id: &str) -> Item {
+        serde_json::from_value(json!({"itemId": id})).unwrap()
+    }
+
+    #[test]
+    fn advances_segments_past_pages() {
+        let mut progress = ScanProgress::new(3);
+        progress.advance(&page(1, Some(key("a"))));
+        progress.advance(&page(2, None));
+        progress.advance(&page(2, None));
+
+        let segments: Vec<_> = progress.pending.iter().map(|p| p.segment).collect();
+        assert_eq!(segments, [0, 1]);
+        assert_eq!(progress.pending[0].start_key, None);
+        assert_eq!(progress.pending[1].start_key, Some(key("a")));
+        assert!(progress.fits(3));
+        assert!(!progress.fits(2));
+
+        let first = progress.take(1);
+        assert_eq!(first.total, 3);
+        assert_eq!(first.pending.len(), 1);
+        assert_eq!(first.pending[0].segment, 0);
+        assert_eq!(progress.take(5).pending.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn reads_every_segment_to_the_end() {
+        let store = MemoryStore::new(KeySchema {
+            pk: "itemId".into(),
+            sk: None,
+        });
+        for n in 0..20 {
+            store.put(key(&n.to_string()), None).await.unwrap();
+        }
+
+        let mut progress = ScanProgress::new(4);
+        let request = PageRequest {
+            limit: Some(3),
+            ..Default::default()
+        };
+        let mut pages = parallel_scan(Arc::new(store), progress.clone(), request, 2, None, None);
+        let mut ids = vec![];
+        while let Some(page) = pages.recv().await {
+            let page = page.unwrap();
+            assert!(page.items.len() <= 3);
+            progress.advance(&page);
+            ids.extend(page.items);
+        }
+
+        assert!(progress.pending.is_empty());
+        assert_eq!(ids.len(), 20);
+    }
+}
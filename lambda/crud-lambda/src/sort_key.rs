@@ -0,0 +1,96 @@
+use serde::Deserialize;
+
+use crate::{
+    error::ApiError,
+    store::{Comparison, SortCondition},
+};
+
+/// Order of the items of a query, by sort key.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    #[default]
+    Asc,
+    Desc,
+}
+
+/// Sort key conditions of `GET /items/:id` on tables with a sort key: either
+/// `?begins_with=2024-` or a `?from=` and `?to=` range, inclusive, with either
+/// end left open if not given.
+#[derive(Debug, Deserialize)]
+pub struct SortKeyParams {
+    pub begins_with: Option<String>,
+    pub from: Option<String>,
+    pub to: Option<String>,
+    #[serde(default)]
+    pub order: Order,
+}
+
+impl SortKeyParams {
+    /// Query parameters taken by the sort key condition rather than filtering.
+    pub const NAMES: &'static [&'static str] = &["begins_with", "from", "to", "order"];
+
+    pub fn condition(&self) -> Result<Option<SortCondition>, ApiError> {
+        let condition = match (&self.begins_with, &self.from, &self.to) {
+            (Some(_), Some(_), _) | (Some(_), _, Some(_)) => {
+                return Err(ApiError::BadRequest(
+                    "begins_with cannot be combined with from or to".into(),
+                ))
+            }
+            (Some(prefix), None, None) => SortCondition::BeginsWith(prefix.clone()),
+            (None, Some(from), Some(to)) if from > to => {
+                return Err(ApiError::BadRequest("from must not be after to".into()))
+            }
+            (None, Some(from), Some(to)) => SortCondition::Between(from.clone(), to.clone()),
+            (None, Some(from), None) => {
+                SortCondition::Compare(Comparison::GreaterOrEqual, from.clone())
+            }
+            (None, None, Some(to)) => SortCondition::Compare(Comparison::LessOrEqual, to.clone()),
+            (None, None, None) => return Ok(None),
+        };
+        Ok(Some(condition))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(query: &str) -> SortKeyParams {
+        serde_urlencoded::from_str(query).unwrap()
+    }
+
+    /// Which of a few sort keys the condition of `query` selects.
+    fn selected(query: &str) -> Vec<&'static str> {
+        let condition = params(query).condition().unwrap().unwrap();
+        ["2023-12", "2024-01", "2024-02", "2024-03", "2025"]
+            .into_iter()
+            .filter(|sk| condition.matches(sk))
+            .collect()
+    }
+
+    #[test]
+    fn compiles_sort_key_conditions() {
+        assert_eq!(
+            selected("begins_with=2024-"),
+            ["2024-01", "2024-02", "2024-03"]
+        );
+        assert_eq!(selected("from=2024-02&to=2024-03"), ["2024-02", "2024-03"]);
+        assert_eq!(selected("from=2024-03"), ["2024-03", "2025"]);
+        assert_eq!(selected("to=2024-01"), ["2023-12", "2024-01"]);
+        assert!(params("order=desc").condition().unwrap().is_none());
+        assert_eq!(params("order=desc").order, Order::Desc);
+        assert_eq!(params("").order, Order::Asc);
+    }
+
+    #[test]
+    fn rejects_conflicting_conditions() {
+        for query in ["begins_with=a&from=a", "begins_with=a&to=b", "from=b&to=a"] {
+            assert!(
+                matches!(params(query).condition(), Err(ApiError::BadRequest(_))),
+                "{}",
+                query
+            );
+        }
+    }
+}
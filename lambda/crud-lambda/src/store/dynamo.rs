@@ -0,0 +1,892 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use aws_sdk_dynamodb::{
+    error::{BuildError, ProvideErrorMetadata, SdkError},
+    operation::{put_item::PutItemError, transact_write_items::TransactWriteItemsError},
+    types::{
+        AttributeValue, CancellationReason, ConditionCheck, Delete, DeleteRequest,
+        KeysAndAttributes, Put, PutRequest, ReturnValue, ReturnValuesOnConditionCheckFailure,
+        TransactWriteItem, Update as UpdateAction, WriteRequest,
+    },
+    Client,
+};
+use serde_dynamo::aws_sdk_dynamodb_1::{from_item, from_items, to_attribute_value, to_item};
+use serde_json::Value;
+
+use super::{
+    Comparison, Condition, DocumentPath, IdempotencyRecord, IdempotencyStore, Item, ItemStore, Key,
+    KeyQuery, KeySchema, Operand, Page, PageRequest, RecordedResponse, ReturnValues, Segment,
+    SortCondition, StoreError, TransactWrite, Update, Write,
+};
+
+/// Most keys a single `BatchGetItem` request may ask for.
+const BATCH_GET_SIZE: usize = 100;
+/// Most writes a single `BatchWriteItem` request may carry.
+const BATCH_WRITE_SIZE: usize = 25;
+/// How often a batch request is sent before unprocessed entries count as
+/// throttled.
+const BATCH_ATTEMPTS: u32 = 5;
+const BATCH_BASE_DELAY: Duration = Duration::from_millis(50);
+
+pub struct DynamoStore {
+    client: Client,
+    table_name: String,
+    keys: KeySchema,
+}
+
+impl DynamoStore {
+    pub fn new(client: Client, table_name: impl Into<String>, keys: KeySchema) -> Self {
+        Self {
+            client,
+            table_name: table_name.into(),
+            keys,
+        }
+    }
+
+    fn key(&self, key: &Key) -> HashMap<String, AttributeValue> {
+        let mut attributes =
+            HashMap::from([(self.keys.pk.clone(), AttributeValue::S(key.id.clone()))]);
+        if let (Some(sk), Some(value)) = (&self.keys.sk, &key.sk) {
+            attributes.insert(sk.clone(), AttributeValue::S(value.clone()));
+        }
+        attributes
+    }
+
+    /// The key a batch write request is for.
+    fn write_key(&self, request: &WriteRequest) -> Option<Key> {
+        let attributes = match (&request.put_request, &request.delete_request) {
+            (Some(put), _) => &put.item,
+            (_, Some(delete)) => &delete.key,
+            _ => return None,
+        };
+        let string = |name: &str| attributes.get(name)?.as_s().ok().cloned();
+        let sk = match &self.keys.sk {
+            Some(sk) => Some(string(sk)?),
+            None => None,
+        };
+        Some(Key::new(string(&self.keys.pk)?, sk))
+    }
+
+    fn write_request(&self, write: Write) -> Result<WriteRequest, StoreError> {
+        let request = match write {
+            Write::Put(item) => WriteRequest::builder()
+                .put_request(
+                    PutRequest::builder()
+                        .set_item(Some(to_item(item)?))
+                        .build()?,
+                )
+                .build(),
+            Write::Delete(key) => WriteRequest::builder()
+                .delete_request(
+                    DeleteRequest::builder()
+                        .set_key(Some(self.key(&key)))
+                        .build()?,
+                )
+                .build(),
+        };
+        Ok(request)
+    }
+}
+
+/// Placeholders used by the expressions of one request. Attribute names and
+/// values only ever appear in `ExpressionAttributeNames` and
+/// `ExpressionAttributeValues`, never in the expression text, so any
+/// attribute name is safe to use.
+#[derive(Default)]
+struct Expressions {
+    names: HashMap<String, String>,
+    values: HashMap<String, AttributeValue>,
+}
+
+impl Expressions {
+    /// The `#nN` placeholder for `name`, reused if the name was seen before.
+    fn name(&mut self, name: &str) -> String {
+        let next = format!("#n{}", self.names.len());
+        self.names.entry(name.to_string()).or_insert(next).clone()
+    }
+
+    /// Registers `value` under a fresh `:vN` placeholder and returns it.
+    fn value(&mut self, value: &Value) -> Result<String, StoreError> {
+        let placeholder = format!(":v{}", self.values.len());
+        self.values
+            .insert(placeholder.clone(), to_attribute_value(value)?);
+        Ok(placeholder)
+    }
+
+    /// Renders `path` as a document path such as `#n0.#n1[0]`.
+    fn path(&mut self, path: &DocumentPath) -> String {
+        let mut expression = self.name(path.top());
+        for segment in path.segments() {
+            match segment {
+                Segment::Attr(name) => {
+                    let name = self.name(name);
+                    expression.push('.');
+                    expression.push_str(&name);
+                }
+                Segment::Index(index) => expression.push_str(&format!("[{}]", index)),
+            }
+        }
+        expression
+    }
+
+    fn operand(&mut self, operand: &Operand) -> Result<String, StoreError> {
+        Ok(match operand {
+            Operand::Value(v) => self.value(v)?,
+            Operand::Path(path) => self.path(path),
+            Operand::Append(path, elements) => format!(
+                "list_append({}, {})",
+                self.path(path),
+                self.value(&Value::from(elements.clone()))?
+            ),
+            Operand::AppendOrCreate(path, elements) => format!(
+                "list_append(if_not_exists({}, {}), {})",
+                self.path(path),
+                self.value(&Value::Array(vec![]))?,
+                self.value(&Value::from(elements.clone()))?
+            ),
+        })
+    }
+
+    fn condition(&mut self, condition: &Condition) -> Result<String, StoreError> {
+        Ok(match condition {
+            Condition::Exists(path) => format!("attribute_exists({})", self.path(path)),
+            Condition::NotExists(path) => format!("attribute_not_exists({})", self.path(path)),
+            Condition::Equals(path, v) => format!("{} = {}", self.path(path), self.value(v)?),
+            Condition::NotEquals(path, v) => format!("{} <> {}", self.path(path), self.value(v)?),
+            Condition::Compare(path, comparison, v) => format!(
+                "{} {} {}",
+                self.path(path),
+                operator(*comparison),
+                self.value(v)?
+            ),
+            Condition::BeginsWith(path, prefix) => format!(
+                "begins_with({}, {})",
+                self.path(path),
+                self.value(&Value::from(prefix.as_str()))?
+            ),
+            Condition::Contains(path, v) => {
+                format!("contains({}, {})", self.path(path), self.value(v)?)
+            }
+            Condition::And(conditions) => conditions
+                .iter()
+                .map(|condition| self.condition(condition).map(|c| format!("({})", c)))
+                .collect::<Result<Vec<_>, _>>()?
+                .join(" AND "),
+        })
+    }
+
+    /// Renders the key condition selecting the items of `query`, on the key
+    /// attributes `keys` of the table or index queried.
+    fn key_condition(&mut self, keys: &KeySchema, query: &KeyQuery) -> Result<String, StoreError> {
+        let mut expression = format!(
+            "{} = {}",
+            self.name(&keys.pk),
+            self.value(&Value::from(query.id.as_str()))?
+        );
+        let sk = match (&keys.sk, &query.sort) {
+            (_, None) => return Ok(expression),
+            (Some(sk), Some(_)) => self.name(sk),
+            (None, Some(_)) => {
+                return Err(StoreError::Validation(
+                    "a sort key condition needs a sort key".into(),
+                ))
+            }
+        };
+        let sort = match &query.sort {
+            Some(SortCondition::Compare(comparison, v)) => Some(format!(
+                "{} {} {}",
+                sk,
+                operator(*comparison),
+                self.value(&Value::from(v.as_str()))?
+            )),
+            Some(SortCondition::Between(low, high)) => Some(format!(
+                "{} BETWEEN {} AND {}",
+                sk,
+                self.value(&Value::from(low.as_str()))?,
+                self.value(&Value::from(high.as_str()))?
+            )),
+            Some(SortCondition::BeginsWith(prefix)) => Some(format!(
+                "begins_with({}, {})",
+                sk,
+                self.value(&Value::from(prefix.as_str()))?
+            )),
+            None => None,
+        };
+        if let Some(sort) = sort {
+            expression.push_str(&format!(" AND {}", sort));
+        }
+        Ok(expression)
+    }
+
+    fn update(&mut self, update: &Update) -> Result<String, StoreError> {
+        let mut set = vec![];
+        for (path, operand) in &update.set {
+            set.push(format!("{} = {}", self.path(path), self.operand(operand)?));
+        }
+
+        let mut remove = vec![];
+        for path in &update.remove {
+            remove.push(self.path(path));
+        }
+
+        let mut add = vec![];
+        for (k, v) in &update.add {
+            add.push(format!("{} {}", self.name(k), self.value(v)?));
+        }
+
+        let mut expression = String::new();
+        if !set.is_empty() {
+            expression.push_str(&format!("SET {} ", set.join(", ")));
+        }
+        if !remove.is_empty() {
+            expression.push_str(&format!("REMOVE {} ", remove.join(", ")));
+        }
+        if !add.is_empty() {
+            expression.push_str(&format!("ADD {} ", add.join(", ")));
+        }
+        Ok(expression)
+    }
+
+    fn projection(&mut self, paths: &[DocumentPath]) -> String {
+        paths
+            .iter()
+            .map(|path| self.path(path))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn names(&self) -> Option<HashMap<String, String>> {
+        let names: HashMap<_, _> = self
+            .names
+            .iter()
+            .map(|(name, placeholder)| (placeholder.clone(), name.clone()))
+            .collect();
+        (!names.is_empty()).then_some(names)
+    }
+
+    fn values(&self) -> Option<HashMap<String, AttributeValue>> {
+        (!self.values.is_empty()).then(|| self.values.clone())
+    }
+}
+
+/// Keeps idempotency records in their own table, keyed by `key` and expired
+/// through DynamoDB TTL on `expires_at`. TTL deletes lag behind, so expired
+/// records are also treated as absent when claiming.
+pub struct DynamoIdempotencyStore {
+    client: Client,
+    table_name: String,
+}
+
+impl DynamoIdempotencyStore {
+    pub fn new(client: Client, table_name: impl Into<String>) -> Self {
+        Self {
+            client,
+            table_name: table_name.into(),
+        }
+    }
+
+    fn key(&self, key: &str) -> HashMap<String, AttributeValue> {
+        HashMap::from([("key".to_string(), AttributeValue::S(key.to_string()))])
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for DynamoIdempotencyStore {
+    async fn claim(
+        &self,
+        key: &str,
+        request_hash: &str,
+        expires_at: u64,
+    ) -> Result<Option<IdempotencyRecord>, StoreError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut item = self.key(key);
+        item.insert(
+            "request_hash".into(),
+            AttributeValue::S(request_hash.to_string()),
+        );
+        item.insert(
+            "expires_at".into(),
+            AttributeValue::N(expires_at.to_string()),
+        );
+
+        let result = self
+            .client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(item))
+            .condition_expression("attribute_not_exists(#key) OR #expires_at <= :now")
+            .expression_attribute_names("#key", "key")
+            .expression_attribute_names("#expires_at", "expires_at")
+            .expression_attribute_values(":now", AttributeValue::N(now.to_string()))
+            .return_values_on_condition_check_failure(ReturnValuesOnConditionCheckFailure::AllOld)
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(None),
+            Err(e) => match e.as_service_error() {
+                Some(PutItemError::ConditionalCheckFailedException(failed)) => {
+                    Ok(Some(from_item(failed.item.clone().unwrap_or_default())?))
+                }
+                _ => Err(e.into()),
+            },
+        }
+    }
+
+    async fn record(
+        &self,
+        key: &str,
+        response: &RecordedResponse,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        self.client
+            .update_item()
+            .table_name(&self.table_name)
+            .set_key(Some(self.key(key)))
+            .update_expression("SET #response = :response, #expires_at = :expires_at")
+            .expression_attribute_names("#response", "response")
+            .expression_attribute_names("#expires_at", "expires_at")
+            .expression_attribute_values(":response", to_attribute_value(response)?)
+            .expression_attribute_values(":expires_at", AttributeValue::N(expires_at.to_string()))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> Result<(), StoreError> {
+        self.client
+            .delete_item()
+            .table_name(&self.table_name)
+            .set_key(Some(self.key(key)))
+            .send()
+            .await?;
+
+        Ok(())
+    }
+}
+
+fn operator(comparison: Comparison) -> &'static str {
+    match comparison {
+        Comparison::Less => "<",
+        Comparison::LessOrEqual => "<=",
+        Comparison::Greater => ">",
+        Comparison::GreaterOrEqual => ">=",
+    }
+}
+
+/// Waits before resending the unprocessed entries of a batch request, doubling
+/// the delay each time, and gives up as throttled once the attempts run out.
+async fn backoff(attempt: u32) -> Result<(), StoreError> {
+    if attempt >= BATCH_ATTEMPTS {
+        return Err(StoreError::Throttled);
+    }
+    tokio::time::sleep(BATCH_BASE_DELAY * 2u32.pow(attempt - 1)).await;
+    Ok(())
+}
+
+/// A copy of `e` to report for each write of a failed batch request. Errors
+/// without a specific meaning to the caller only keep their message.
+fn for_each_write(e: &StoreError) -> StoreError {
+    match e {
+        StoreError::ConditionFailed => StoreError::ConditionFailed,
+        StoreError::Conflict(message) => StoreError::Conflict(message.clone()),
+        StoreError::Validation(message) => StoreError::Validation(message.clone()),
+        StoreError::Throttled => StoreError::Throttled,
+        e => StoreError::Backend(e.to_string().into()),
+    }
+}
+
+impl From<BuildError> for StoreError {
+    fn from(e: BuildError) -> Self {
+        StoreError::Backend(Box::new(e))
+    }
+}
+
+/// Why DynamoDB canceled one write of a transaction, or `None` if that write
+/// was fine.
+fn cancellation_reason(reason: CancellationReason) -> Option<StoreError> {
+    let message = reason.message.unwrap_or_default();
+    match reason.code.as_deref() {
+        None | Some("None") => None,
+        Some("ConditionalCheckFailed") => Some(StoreError::ConditionFailed),
+        Some("TransactionConflict") => Some(StoreError::Conflict(message)),
+        Some("ValidationError") => Some(StoreError::Validation(message)),
+        Some("ThrottlingError" | "ProvisionedThroughputExceeded" | "RequestLimitExceeded") => {
+            Some(StoreError::Throttled)
+        }
+        Some(code) => Some(StoreError::Backend(format!("{}: {}", code, message).into())),
+    }
+}
+
+impl<E, R> From<SdkError<E, R>> for StoreError
+where
+    E: std::error::Error + ProvideErrorMetadata + Send + Sync + 'static,
+    R: Debug + Send + Sync + 'static,
+{
+    fn from(e: SdkError<E, R>) -> Self {
+        let message = e.message().unwrap_or_default().to_string();
+        match e.code() {
+            Some("ConditionalCheckFailedException") => StoreError::ConditionFailed,
+            Some("TransactionConflictException") => StoreError::Conflict(message),
+            Some("ValidationException") => StoreError::Validation(message),
+            Some(
+                "ProvisionedThroughputExceededException"
+                | "RequestLimitExceeded"
+                | "ThrottlingException",
+            ) => StoreError::Throttled,
+            _ => StoreError::Backend(Box::new(e)),
+        }
+    }
+}
+
+#[async_trait]
+impl ItemStore for DynamoStore {
+    async fn put(&self, item: Item, condition: Option<Condition>) -> Result<(), StoreError> {
+        let mut expressions = Expressions::default();
+        let condition_expression = condition
+            .map(|condition| expressions.condition(&condition))
+            .transpose()?;
+
+        self.client
+            .put_item()
+            .table_name(&self.table_name)
+            .set_item(Some(to_item(item)?))
+            .set_condition_expression(condition_expression)
+            .set_expression_attribute_names(expressions.names())
+            .set_expression_attribute_values(expressions.values())
+            .send()
+            .await?;
+
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        key: &Key,
+        projection: Option<&[DocumentPath]>,
+    ) -> Result<Option<Item>, StoreError> {
+        let mut expressions = Expressions::default();
+        let projection_expression = projection.map(|paths| expressions.projection(paths));
+
+        let item = self
+            .client
+            .get_item()
+            .table_name(&self.table_name)
+            .set_key(Some(self.key(key)))
+            .set_projection_expression(projection_expression)
+            .set_expression_attribute_names(expressions.names())
+            .send()
+            .await?
+            .item;
+
+        Ok(item.map(from_item).transpose()?)
+    }
+
+    async fn batch_get(&self, keys: &[Key]) -> Result<Vec<Item>, StoreError> {
+        let mut items = vec![];
+
+        for chunk in keys.chunks(BATCH_GET_SIZE) {
+            let mut keys: Vec<_> = chunk.iter().map(|key| self.key(key)).collect();
+            let mut attempt = 0;
+
+            while !keys.is_empty() {
+                if attempt > 0 {
+                    backoff(attempt).await?;
+                }
+                attempt += 1;
+
+                let request = KeysAndAttributes::builder().set_keys(Some(keys)).build()?;
+                let output = self
+                    .client
+                    .batch_get_item()
+                    .request_items(&self.table_name, request)
+                    .send()
+                    .await?;
+
+                if let Some(found) = output
+                    .responses
+                    .and_then(|mut responses| responses.remove(&self.table_name))
+                {
+                    items.extend(from_items::<Item>(found)?);
+                }
+                keys = output
+                    .unprocessed_keys
+                    .and_then(|mut unprocessed| unprocessed.remove(&self.table_name))
+                    .map(|unprocessed| unprocessed.keys)
+                    .unwrap_or_default();
+            }
+        }
+
+        Ok(items)
+    }
+
+    async fn scan(&self, request: PageRequest) -> Result<Page, StoreError> {
+        let mut expressions = Expressions::default();
+        let filter_expression = request
+            .filter
+            .map(|filter| expressions.condition(&filter))
+            .transpose()?;
+        let projection_expression = request
+            .projection
+            .map(|paths| expressions.projection(&paths));
+
+        let output = self
+            .client
+            .scan()
+            .table_name(&self.table_name)
+            .set_filter_expression(filter_expression)
+            .set_projection_expression(projection_expression)
+            .set_expression_attribute_names(expressions.names())
+            .set_expression_attribute_values(expressions.values())
+            .set_limit(
+                request
+                    .limit
+                    .map(|limit| limit.try_into().unwrap_or(i32::MAX)),
+            )
+            .set_exclusive_start_key(request.start_key.map(to_item).transpose()?)
+            .set_segment(
+                request
+                    .segment
+                    .map(|segment| segment.segment.try_into().unwrap_or(i32::MAX)),
+            )
+            .set_total_segments(
+                request
+                    .segment
+                    .map(|segment| segment.total.try_into().unwrap_or(i32::MAX)),
+            )
+            .send()
+            .await?;
+
+        Ok(Page {
+            items: from_items(output.items.unwrap_or_default())?,
+            last_key: output.last_evaluated_key.map(from_item).transpose()?,
+        })
+    }
+
+    async fn query(&self, query: KeyQuery, request: PageRequest) -> Result<Page, StoreError> {
+        let keys = query.index.as_ref().map_or(&self.keys, |index| &index.keys);
+        let mut expressions = Expressions::default();
+        let key_condition_expression = expressions.key_condition(keys, &query)?;
+        let filter_expression = request
+            .filter
+            .map(|filter| expressions.condition(&filter))
+            .transpose()?;
+        let projection_expression = request
+            .projection
+            .map(|paths| expressions.projection(&paths));
+
+        let output = self
+            .client
+            .query()
+            .table_name(&self.table_name)
+            .set_index_name(query.index.map(|index| index.name))
+            .key_condition_expression(key_condition_expression)
+            .scan_index_forward(query.forward)
+            .set_filter_expression(filter_expression)
+            .set_projection_expression(projection_expression)
+            .set_expression_attribute_names(expressions.names())
+            .set_expression_attribute_values(expressions.values())
+            .set_limit(
+                request
+                    .limit
+                    .map(|limit| limit.try_into().unwrap_or(i32::MAX)),
+            )
+            .set_exclusive_start_key(request.start_key.map(to_item).transpose()?)
+            .send()
+            .await?;
+
+        Ok(Page {
+            items: from_items(output.items.unwrap_or_default())?,
+            last_key: output.last_evaluated_key.map(from_item).transpose()?,
+        })
+    }
+
+    async fn delete(
+        &self,
+        key: &Key,
+        condition: Option<Condition>,
+        returns: ReturnValues,
+    ) -> Result<Option<Item>, StoreError> {
+        let mut expressions = Expressions::default();
+        let condition_expression = condition
+            .map(|condition| expressions.condition(&condition))
+            .transpose()?;
+
+        let output = self
+            .client
+            .delete_item()
+            .table_name(&self.table_name)
+            .set_key(Some(self.key(key)))
+            .set_condition_expression(condition_expression)
+            .set_expression_attribute_names(expressions.names())
+            .set_expression_attribute_values(expressions.values())
+            .set_return_values((returns == ReturnValues::AllOld).then_some(ReturnValue::AllOld))
+            .send()
+            .await?;
+
+        Ok(output.attributes.map(from_item).transpose()?)
+    }
+
+    async fn batch_write(&self, writes: Vec<Write>) -> Vec<Result<(), StoreError>> {
+        let mut outcomes: Vec<Result<(), StoreError>> = vec![];
+        let mut writes = writes.into_iter().peekable();
+
+        while writes.peek().is_some() {
+            // Writes still to be sent, by their index into `outcomes`.
+            let mut pending = vec![];
+            for write in writes.by_ref().take(BATCH_WRITE_SIZE) {
+                match self.write_request(write) {
+                    Ok(request) => {
+                        pending.push((outcomes.len(), request));
+                        outcomes.push(Ok(()));
+                    }
+                    Err(e) => outcomes.push(Err(e)),
+                }
+            }
+
+            let mut attempt = 0;
+            while !pending.is_empty() {
+                let requests = pending.iter().map(|(_, request)| request.clone()).collect();
+                let sent = async {
+                    if attempt > 0 {
+                        backoff(attempt).await?;
+                    }
+                    Ok::<_, StoreError>(
+                        self.client
+                            .batch_write_item()
+                            .request_items(&self.table_name, requests)
+                            .send()
+                            .await?,
+                    )
+                };
+                let output = match sent.await {
+                    Ok(output) => output,
+                    Err(e) => {
+                        for (i, _) in &pending {
+                            outcomes[*i] = Err(for_each_write(&e));
+                        }
+                        break;
+                    }
+                };
+                attempt += 1;
+
+                let unprocessed: HashSet<Key> = output
+                    .unprocessed_items
+                    .and_then(|mut unprocessed| unprocessed.remove(&self.table_name))
+                    .unwrap_or_default()
+                    .iter()
+                    .filter_map(|request| self.write_key(request))
+                    .collect();
+                pending.retain(|(_, request)| {
+                    self.write_key(request)
+                        .is_some_and(|key| unprocessed.contains(&key))
+                });
+            }
+        }
+
+        outcomes
+    }
+
+    async fn transact_write(&self, writes: Vec<TransactWrite>) -> Result<(), StoreError> {
+        let mut items = vec![];
+        for write in writes {
+            let mut expressions = Expressions::default();
+            let item = match write {
+                TransactWrite::Put { item, condition } => {
+                    let condition_expression = condition
+                        .map(|condition| expressions.condition(&condition))
+                        .transpose()?;
+                    let put = Put::builder()
+                        .table_name(&self.table_name)
+                        .set_item(Some(to_item(item)?))
+                        .set_condition_expression(condition_expression)
+                        .set_expression_attribute_names(expressions.names())
+                        .set_expression_attribute_values(expressions.values())
+                        .build()?;
+                    TransactWriteItem::builder().put(put).build()
+                }
+                TransactWrite::Update {
+                    key,
+                    update,
+                    condition,
+                } => {
+                    let update_expression = expressions.update(&update)?;
+                    let condition_expression = condition
+                        .map(|condition| expressions.condition(&condition))
+                        .transpose()?;
+                    let update = UpdateAction::builder()
+                        .table_name(&self.table_name)
+                        .set_key(Some(self.key(&key)))
+                        .update_expression(update_expression)
+                        .set_condition_expression(condition_expression)
+                        .set_expression_attribute_names(expressions.names())
+                        .set_expression_attribute_values(expressions.values())
+                        .build()?;
+                    TransactWriteItem::builder().update(update).build()
+                }
+                TransactWrite::Delete { key, condition } => {
+                    let condition_expression = condition
+                        .map(|condition| expressions.condition(&condition))
+                        .transpose()?;
+                    let delete = Delete::builder()
+                        .table_name(&self.table_name)
+                        .set_key(Some(self.key(&key)))
+                        .set_condition_expression(condition_expression)
+                        .set_expression_attribute_names(expressions.names())
+                        .set_expression_attribute_values(expressions.values())
+                        .build()?;
+                    TransactWriteItem::builder().delete(delete).build()
+                }
+                TransactWrite::Check { key, condition } => {
+                    let check = ConditionCheck::builder()
+                        .table_name(&self.table_name)
+                        .set_key(Some(self.key(&key)))
+                        .condition_expression(expressions.condition(&condition)?)
+                        .set_expression_attribute_names(expressions.names())
+                        .set_expression_attribute_values(expressions.values())
+                        .build()?;
+                    TransactWriteItem::builder().condition_check(check).build()
+                }
+            };
+            items.push(item);
+        }
+
+        let result = self
+            .client
+            .transact_write_items()
+            .set_transact_items(Some(items))
+            .send()
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) => match e.as_service_error() {
+                Some(TransactWriteItemsError::TransactionCanceledException(canceled)) => {
+                    Err(StoreError::Canceled(
+                        canceled
+                            .cancellation_reasons
+                            .clone()
+                            .unwrap_or_default()
+                            .into_iter()
+                            .map(cancellation_reason)
+                            .collect(),
+                    ))
+                }
+                _ => Err(e.into()),
+            },
+        }
+    }
+
+    async fn update(
+        &self,
+        key: &Key,
+        update: Update,
+        condition: Option<Condition>,
+        returns: ReturnValues,
+    ) -> Result<Option<Item>, StoreError> {
+        let changed: HashSet<_> = update.paths().map(|path| path.top().to_string()).collect();
+        let mut expressions = Expressions::default();
+        let update_expression = expressions.update(&update)?;
+        let condition_expression = condition
+            .map(|condition| expressions.condition(&condition))
+            .transpose()?;
+
+        let output = self
+            .client
+            .update_item()
+            .table_name(&self.table_name)
+            .set_key(Some(self.key(key)))
+            .update_expression(update_expression)
+            .set_condition_expression(condition_expression)
+            .set_expression_attribute_names(expressions.names())
+            .set_expression_attribute_values(expressions.values())
+            .return_values(match returns {
+                ReturnValues::None => ReturnValue::None,
+                ReturnValues::AllNew | ReturnValues::UpdatedNew => ReturnValue::AllNew,
+                ReturnValues::AllOld => ReturnValue::AllOld,
+            })
+            .send()
+            .await?;
+
+        // UPDATED_NEW would only hold the changed parts of nested attributes,
+        // so pick the whole top-level attributes out of the new item instead.
+        let mut attributes = output.attributes;
+        if returns == ReturnValues::UpdatedNew {
+            let attributes = attributes.get_or_insert_with(HashMap::new);
+            attributes.retain(|k, _| changed.contains(k));
+        }
+        Ok(attributes.map(from_item).transpose()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn keeps_names_and_values_out_of_expressions() {
+        let mut expressions = Expressions::default();
+        let update = Update {
+            set: vec![
+                (DocumentPath::attr("name"), Operand::Value(json!("x"))),
+                (
+                    DocumentPath::attr("a.b").child("name").index(2),
+                    Operand::Value(json!(1)),
+                ),
+            ],
+            remove: vec![DocumentPath::attr("status")],
+            add: serde_json::from_value(json!({"count": 1})).unwrap(),
+        };
+
+        assert_eq!(
+            expressions.update(&update).unwrap(),
+            "SET #n0 = :v0, #n1.#n0[2] = :v1 REMOVE #n2 ADD #n3 :v2 "
+        );
+        let names: HashMap<_, _> = expressions
+            .names
+            .iter()
+            .map(|(name, placeholder)| (placeholder.as_str(), name.as_str()))
+            .collect();
+        assert_eq!(
+            names,
+            HashMap::from([
+                ("#n0", "name"),
+                ("#n1", "a.b"),
+                ("#n2", "status"),
+                ("#n3", "count")
+            ])
+        );
+        assert_eq!(expressions.values[":v0"], AttributeValue::S("x".into()));
+        assert_eq!(expressions.values[":v2"], AttributeValue::N("1".into()));
+    }
+
+    #[test]
+    fn renders_conditions() {
+        let mut expressions = Expressions::default();
+        let condition = Condition::And(vec![
+            Condition::Exists(DocumentPath::attr("id")),
+            Condition::Compare(
+                DocumentPath::attr("n"),
+                Comparison::GreaterOrEqual,
+                json!(2),
+            ),
+            Condition::BeginsWith(DocumentPath::attr("s"), "pre".into()),
+        ]);
+
+        assert_eq!(
+            expressions.condition(&condition).unwrap(),
+            "(attribute_exists(#n0)) AND (#n1 >= :v0) AND (begins_with(#n2, :v1))"
+        );
+    }
+}
@@ -0,0 +1,166 @@
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::Item;
+
+/// Everything but the unreserved characters of RFC 3986, so that a key value
+/// always stays a single path segment.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Identifies an item: its partition key value, plus its sort key value on
+/// tables that have one. In JSON it is the bare id, or `{"id": .., "sk": ..}`
+/// with a sort key.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(from = "KeyRepr", into = "KeyRepr")]
+pub struct Key {
+    pub id: String,
+    pub sk: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum KeyRepr {
+    Id(String),
+    Composite { id: String, sk: String },
+}
+
+impl From<KeyRepr> for Key {
+    fn from(repr: KeyRepr) -> Self {
+        match repr {
+            KeyRepr::Id(id) => Key { id, sk: None },
+            KeyRepr::Composite { id, sk } => Key { id, sk: Some(sk) },
+        }
+    }
+}
+
+impl From<Key> for KeyRepr {
+    fn from(key: Key) -> Self {
+        match key.sk {
+            None => KeyRepr::Id(key.id),
+            Some(sk) => KeyRepr::Composite { id: key.id, sk },
+        }
+    }
+}
+
+impl Key {
+    pub fn new(id: impl Into<String>, sk: Option<String>) -> Self {
+        Self { id: id.into(), sk }
+    }
+
+    /// The key as it appears in item routes, `id` or `id/sk`, with each
+    /// value percent-encoded.
+    pub fn path(&self) -> String {
+        let id = utf8_percent_encode(&self.id, SEGMENT);
+        match &self.sk {
+            None => id.to_string(),
+            Some(sk) => format!("{}/{}", id, utf8_percent_encode(sk, SEGMENT)),
+        }
+    }
+
+    /// Reads a key written as by `path`, or `None` if it is not one.
+    pub fn from_path(path: &str) -> Option<Self> {
+        let decode = |segment: &str| {
+            let value = percent_decode_str(segment).decode_utf8().ok()?;
+            (!value.is_empty()).then(|| value.into_owned())
+        };
+        match path.split_once('/') {
+            None => Some(Key::new(decode(path)?, None)),
+            Some((id, sk)) if !sk.contains('/') => Some(Key::new(decode(id)?, Some(decode(sk)?))),
+            Some(_) => None,
+        }
+    }
+}
+
+/// Names of the key attributes of the table. Both keys are strings.
+#[derive(Debug, Clone)]
+pub struct KeySchema {
+    pub pk: String,
+    pub sk: Option<String>,
+}
+
+impl KeySchema {
+    /// The key of `item`, if it has every key attribute as a string.
+    pub fn key_of(&self, item: &Item) -> Option<Key> {
+        let id = item.get(&self.pk)?.as_str()?;
+        let sk = match &self.sk {
+            Some(sk) => Some(item.get(sk)?.as_str()?.to_string()),
+            None => None,
+        };
+        Some(Key::new(id, sk))
+    }
+
+    /// The key attributes making up `key`.
+    pub fn attributes(&self, key: &Key) -> Item {
+        let mut attributes = Item::from_iter([(self.pk.clone(), Value::from(key.id.as_str()))]);
+        if let (Some(sk), Some(value)) = (&self.sk, &key.sk) {
+            attributes.insert(sk.clone(), Value::from(value.as_str()));
+        }
+        attributes
+    }
+
+    /// Whether `attr` is one of the key attributes.
+    pub fn is_key(&self, attr: &str) -> bool {
+        attr == self.pk || self.sk.as_deref() == Some(attr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn encodes_each_path_segment() {
+        assert_eq!(Key::new("a-1_b.c~", None).path(), "a-1_b.c~");
+        assert_eq!(
+            Key::new("cust 1/x", Some("ORDER#2024-02?é".into())).path(),
+            "cust%201%2Fx/ORDER%232024-02%3F%C3%A9"
+        );
+    }
+
+    #[test]
+    fn reads_keys_back_from_paths() {
+        let key = Key::new("cust 1/x", Some("ORDER#2024-02?é".into()));
+        assert_eq!(Key::from_path(&key.path()), Some(key));
+        assert_eq!(Key::from_path("a%2Cb"), Some(Key::new("a,b", None)));
+        for invalid in ["", "a/", "/1", "a/1/2", "%FF"] {
+            assert_eq!(Key::from_path(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn reads_bare_and_composite_keys() {
+        let keys: Vec<Key> = serde_json::from_value(json!(["a", {"id": "b", "sk": "1"}])).unwrap();
+        assert_eq!(keys, [Key::new("a", None), Key::new("b", Some("1".into()))]);
+        assert_eq!(
+            serde_json::to_value(&keys).unwrap(),
+            json!(["a", {"id": "b", "sk": "1"}])
+        );
+    }
+
+    #[test]
+    fn maps_keys_to_attributes() {
+        let schema = KeySchema {
+            pk: "pk".into(),
+            sk: Some("sk".into()),
+        };
+        let key = Key::new("a", Some("1".into()));
+        let attributes = schema.attributes(&key);
+        assert_eq!(
+            Value::Object(attributes.clone()),
+            json!({"pk": "a", "sk": "1"})
+        );
+        assert_eq!(schema.key_of(&attributes), Some(key));
+
+        let item: Item = serde_json::from_value(json!({"pk": "a", "sk": 1})).unwrap();
+        assert_eq!(schema.key_of(&item), None);
+        assert!(schema.is_key("sk"));
+        assert!(!schema.is_key("name"));
+    }
+}
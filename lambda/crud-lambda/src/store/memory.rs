@@ -0,0 +1,730 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    hash::{DefaultHasher, Hash, Hasher},
+    ops::Bound,
+    sync::RwLock,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use serde_json::Value;
+
+use super::{
+    project, Condition, DocumentPath, IdempotencyRecord, IdempotencyStore, Item, ItemStore, Key,
+    KeyQuery, KeySchema, Operand, Page, PageRequest, RecordedResponse, ReturnValues, ScanSegment,
+    StoreError, TransactWrite, Update, Write,
+};
+
+/// Keeps items in process memory, ordered by their key.
+pub struct MemoryStore {
+    keys: KeySchema,
+    items: RwLock<BTreeMap<Key, Item>>,
+}
+
+impl MemoryStore {
+    pub fn new(keys: KeySchema) -> Self {
+        Self {
+            keys,
+            items: RwLock::default(),
+        }
+    }
+
+    fn key_of(&self, item: &Item) -> Result<Key, StoreError> {
+        self.keys
+            .key_of(item)
+            .ok_or_else(|| StoreError::Validation("item is missing key attributes".into()))
+    }
+
+    /// The item `update` turns `current` into, resolving every operand before
+    /// applying any action.
+    fn apply(&self, key: &Key, current: Option<&Item>, update: Update) -> Result<Item, StoreError> {
+        if update.has_overlapping_paths() {
+            return Err(StoreError::Validation(
+                "Two document paths overlap with each other".into(),
+            ));
+        }
+
+        let original = current
+            .cloned()
+            .unwrap_or_else(|| self.keys.attributes(key));
+        let set = update
+            .set
+            .into_iter()
+            .map(|(path, operand)| Ok((path, resolve(&original, operand)?)))
+            .collect::<Result<Vec<_>, StoreError>>()?;
+
+        let mut item = original;
+        for (path, v) in set {
+            path.set(&mut item, v)?;
+        }
+        // Remove list elements back to front so earlier indices stay valid.
+        let mut remove = update.remove;
+        remove.sort_by(|a, b| b.segments().cmp(a.segments()));
+        for path in &remove {
+            path.remove(&mut item)?;
+        }
+        for (k, v) in update.add {
+            let sum = add_number(item.get(&k), &v)?;
+            item.insert(k, sum);
+        }
+
+        Ok(item)
+    }
+
+    fn start_key(&self, key: &Item) -> Result<Key, StoreError> {
+        self.keys
+            .key_of(key)
+            .ok_or_else(|| StoreError::Validation("invalid start key".into()))
+    }
+
+    /// Reads up to the limit of `read`, then filters and projects those items.
+    /// `keys` are the keys read by, whose attributes resume keys also hold.
+    fn page<'a>(
+        &self,
+        read: impl Iterator<Item = &'a Item>,
+        request: PageRequest,
+        keys: &KeySchema,
+    ) -> Page {
+        let read: Vec<&Item> = read.take(request.limit.unwrap_or(usize::MAX)).collect();
+
+        // Like DynamoDB, hand out a resume key whenever the limit was reached,
+        // and filter only the items read.
+        let last_key = match (request.limit, read.last()) {
+            (Some(limit), Some(last)) if read.len() == limit => self
+                .keys
+                .key_of(last)
+                .zip(keys.key_of(last))
+                .map(|(key, read_key)| {
+                    let mut last_key = self.keys.attributes(&key);
+                    last_key.extend(keys.attributes(&read_key));
+                    last_key
+                }),
+            _ => None,
+        };
+        let items = read
+            .into_iter()
+            .filter(|item| {
+                request
+                    .filter
+                    .as_ref()
+                    .is_none_or(|filter| filter.matches(Some(item)))
+            })
+            .map(|item| match &request.projection {
+                Some(paths) => project(item, paths),
+                None => item.clone(),
+            })
+            .collect();
+
+        Page { items, last_key }
+    }
+}
+
+/// Keeps idempotency records in process memory, alongside their expiry.
+#[derive(Default)]
+pub struct MemoryIdempotencyStore {
+    records: RwLock<HashMap<String, (IdempotencyRecord, u64)>>,
+}
+
+impl MemoryIdempotencyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl IdempotencyStore for MemoryIdempotencyStore {
+    async fn claim(
+        &self,
+        key: &str,
+        request_hash: &str,
+        expires_at: u64,
+    ) -> Result<Option<IdempotencyRecord>, StoreError> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut records = self.records.write().unwrap();
+        match records.get(key) {
+            Some((record, expires_at)) if *expires_at > now => Ok(Some(record.clone())),
+            _ => {
+                let record = IdempotencyRecord {
+                    request_hash: request_hash.to_string(),
+                    response: None,
+                };
+                records.insert(key.to_string(), (record, expires_at));
+                Ok(None)
+            }
+        }
+    }
+
+    async fn record(
+        &self,
+        key: &str,
+        response: &RecordedResponse,
+        expires_at: u64,
+    ) -> Result<(), StoreError> {
+        if let Some((record, expiry)) = self.records.write().unwrap().get_mut(key) {
+            record.response = Some(response.clone());
+            *expiry = expires_at;
+        }
+        Ok(())
+    }
+
+    async fn release(&self, key: &str) -> Result<(), StoreError> {
+        self.records.write().unwrap().remove(key);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ItemStore for MemoryStore {
+    async fn put(&self, item: Item, condition: Option<Condition>) -> Result<(), StoreError> {
+        let key = self.key_of(&item)?;
+        let mut items = self.items.write().unwrap();
+        check(items.get(&key), condition.as_ref())?;
+        items.insert(key, item);
+
+        Ok(())
+    }
+
+    async fn get(
+        &self,
+        key: &Key,
+        projection: Option<&[DocumentPath]>,
+    ) -> Result<Option<Item>, StoreError> {
+        let items = self.items.read().unwrap();
+        Ok(items.get(key).map(|item| match projection {
+            Some(paths) => project(item, paths),
+            None => item.clone(),
+        }))
+    }
+
+    async fn batch_get(&self, keys: &[Key]) -> Result<Vec<Item>, StoreError> {
+        let items = self.items.read().unwrap();
+        Ok(keys
+            .iter()
+            .filter_map(|key| items.get(key).cloned())
+            .collect())
+    }
+
+    async fn scan(&self, request: PageRequest) -> Result<Page, StoreError> {
+        let start = match &request.start_key {
+            Some(key) => Bound::Excluded(self.start_key(key)?),
+            None => Bound::Unbounded,
+        };
+
+        let segment = request.segment;
+        let items = self.items.read().unwrap();
+        Ok(self.page(
+            items
+                .range((start, Bound::Unbounded))
+                .filter(|(key, _)| segment.is_none_or(|segment| in_segment(key, segment)))
+                .map(|(_, item)| item),
+            request,
+            &self.keys,
+        ))
+    }
+
+    async fn query(&self, query: KeyQuery, request: PageRequest) -> Result<Page, StoreError> {
+        let keys = query.index.as_ref().map_or(&self.keys, |index| &index.keys);
+        if query.sort.is_some() && keys.sk.is_none() {
+            return Err(StoreError::Validation(
+                "a sort key condition needs a sort key".into(),
+            ));
+        }
+
+        // Items are in sort key order, and items of an index sharing a sort
+        // key in the order of their table key.
+        let position = |item: &Item| {
+            let sk = match &keys.sk {
+                Some(sk) => Some(item.get(sk)?.as_str()?.to_string()),
+                None => None,
+            };
+            Some((sk, self.keys.key_of(item)?))
+        };
+        let start = request
+            .start_key
+            .as_ref()
+            .map(|key| {
+                position(key).ok_or_else(|| StoreError::Validation("invalid start key".into()))
+            })
+            .transpose()?;
+
+        // Like a DynamoDB index, leave out items without the key attributes.
+        let items = self.items.read().unwrap();
+        let mut partition: Vec<_> = items
+            .values()
+            .filter(|item| keys.key_of(item).is_some_and(|key| key.id == query.id))
+            .filter_map(|item| Some((position(item)?, item)))
+            .filter(|((sk, _), _)| match (&query.sort, sk) {
+                (Some(sort), Some(sk)) => sort.matches(sk),
+                _ => true,
+            })
+            .collect();
+        partition.sort_by(|(a, _), (b, _)| a.cmp(b));
+        if !query.forward {
+            partition.reverse();
+        }
+        // Resume after the start key in the direction of the query.
+        let read = partition
+            .into_iter()
+            .filter(|(position, _)| {
+                start.as_ref().is_none_or(|start| match query.forward {
+                    true => position > start,
+                    false => position < start,
+                })
+            })
+            .map(|(_, item)| item);
+
+        Ok(self.page(read, request, keys))
+    }
+
+    async fn delete(
+        &self,
+        key: &Key,
+        condition: Option<Condition>,
+        returns: ReturnValues,
+    ) -> Result<Option<Item>, StoreError> {
+        let mut items = self.items.write().unwrap();
+        check(items.get(key), condition.as_ref())?;
+        let old = items.remove(key);
+
+        Ok(old.filter(|_| returns == ReturnValues::AllOld))
+    }
+
+    async fn batch_write(&self, writes: Vec<Write>) -> Vec<Result<(), StoreError>> {
+        let mut outcomes = vec![];
+        for write in writes {
+            outcomes.push(match write {
+                Write::Put(item) => self.put(item, None).await,
+                Write::Delete(key) => self
+                    .delete(&key, None, ReturnValues::None)
+                    .await
+                    .map(|_| ()),
+            });
+        }
+        outcomes
+    }
+
+    async fn transact_write(&self, writes: Vec<TransactWrite>) -> Result<(), StoreError> {
+        let mut keys = HashSet::new();
+        for write in &writes {
+            let key = match write {
+                TransactWrite::Put { item, .. } => self.key_of(item)?,
+                TransactWrite::Update { key, .. }
+                | TransactWrite::Delete { key, .. }
+                | TransactWrite::Check { key, .. } => key.clone(),
+            };
+            if !keys.insert(key) {
+                return Err(StoreError::Validation(
+                    "Transaction request cannot include multiple operations on one item".into(),
+                ));
+            }
+        }
+
+        // Every write is for a different item, so each can be worked out
+        // against the stored items on its own, and nothing is stored unless
+        // all of them go through.
+        let mut items = self.items.write().unwrap();
+        let outcomes: Vec<Result<Option<(Key, Option<Item>)>, StoreError>> = writes
+            .into_iter()
+            .map(|write| match write {
+                TransactWrite::Put { item, condition } => {
+                    let key = self.key_of(&item)?;
+                    check(items.get(&key), condition.as_ref())?;
+                    Ok(Some((key, Some(item))))
+                }
+                TransactWrite::Update {
+                    key,
+                    update,
+                    condition,
+                } => {
+                    check(items.get(&key), condition.as_ref())?;
+                    let item = self.apply(&key, items.get(&key), update)?;
+                    Ok(Some((key, Some(item))))
+                }
+                TransactWrite::Delete { key, condition } => {
+                    check(items.get(&key), condition.as_ref())?;
+                    Ok(Some((key, None)))
+                }
+                TransactWrite::Check { key, condition } => {
+                    check(items.get(&key), Some(&condition))?;
+                    Ok(None)
+                }
+            })
+            .collect();
+
+        if outcomes.iter().any(Result::is_err) {
+            return Err(StoreError::Canceled(
+                outcomes.into_iter().map(Result::err).collect(),
+            ));
+        }
+        for (key, item) in outcomes.into_iter().flatten().flatten() {
+            match item {
+                Some(item) => items.insert(key, item),
+                None => items.remove(&key),
+            };
+        }
+
+        Ok(())
+    }
+
+    async fn update(
+        &self,
+        key: &Key,
+        update: Update,
+        condition: Option<Condition>,
+        returns: ReturnValues,
+    ) -> Result<Option<Item>, StoreError> {
+        let changed = changed(&update);
+        let mut items = self.items.write().unwrap();
+        check(items.get(key), condition.as_ref())?;
+        let old = items.get(key).cloned();
+        let item = self.apply(key, old.as_ref(), update)?;
+
+        let returned = match returns {
+            ReturnValues::None => None,
+            ReturnValues::AllNew => Some(item.clone()),
+            ReturnValues::AllOld => old,
+            ReturnValues::UpdatedNew => Some(
+                changed
+                    .into_iter()
+                    .filter_map(|k| Some((k.clone(), item.get(&k)?.clone())))
+                    .collect(),
+            ),
+        };
+        items.insert(key.clone(), item);
+
+        Ok(returned)
+    }
+}
+
+/// Spreads items over scan segments by a hash of their key, which does not
+/// change while the process runs.
+fn in_segment(key: &Key, segment: ScanSegment) -> bool {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish() % u64::from(segment.total.max(1)) == u64::from(segment.segment)
+}
+
+fn check(item: Option<&Item>, condition: Option<&Condition>) -> Result<(), StoreError> {
+    match condition {
+        Some(condition) if !condition.matches(item) => Err(StoreError::ConditionFailed),
+        _ => Ok(()),
+    }
+}
+
+fn resolve(item: &Item, operand: Operand) -> Result<Value, StoreError> {
+    let invalid = || {
+        StoreError::Validation(
+            "The provided expression refers to an attribute that does not exist in the item".into(),
+        )
+    };
+
+    let create = matches!(operand, Operand::AppendOrCreate(..));
+    match operand {
+        Operand::Value(v) => Ok(v),
+        Operand::Path(path) => path.get(item).cloned().ok_or_else(invalid),
+        Operand::Append(path, elements) | Operand::AppendOrCreate(path, elements) => {
+            let mut list = match path.get(item) {
+                Some(list) => list,
+                None if create => return Ok(Value::Array(elements)),
+                None => return Err(invalid()),
+            }
+            .as_array()
+            .ok_or_else(|| {
+                StoreError::Validation(
+                    "An operand in the update expression has an incorrect data type".into(),
+                )
+            })?
+            .clone();
+            list.extend(elements);
+            Ok(Value::Array(list))
+        }
+    }
+}
+
+/// Top-level attributes `update` writes to.
+fn changed(update: &Update) -> BTreeSet<String> {
+    update.paths().map(|path| path.top().to_string()).collect()
+}
+
+fn add_number(current: Option<&Value>, delta: &Value) -> Result<Value, StoreError> {
+    let invalid = || StoreError::Validation("ADD requires number operands".into());
+    let current = match current {
+        Some(current) => current.as_number().ok_or_else(invalid)?,
+        None => return delta.is_number().then(|| delta.clone()).ok_or_else(invalid),
+    };
+    let delta = delta.as_number().ok_or_else(invalid)?;
+
+    let sum = match (current.as_i64(), delta.as_i64()) {
+        (Some(a), Some(b)) => a.checked_add(b).map(Value::from),
+        _ => None,
+    };
+    Ok(sum.unwrap_or_else(|| {
+        Value::from(current.as_f64().unwrap_or_default() + delta.as_f64().unwrap_or_default())
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::store::SortCondition;
+
+    fn store() -> MemoryStore {
+        MemoryStore::new(KeySchema {
+            pk: "id".into(),
+            sk: None,
+        })
+    }
+
+    fn item(value: Value) -> Item {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[tokio::test]
+    async fn puts_gets_and_deletes() {
+        let store = store();
+        let key = Key::new("a", None);
+
+        store
+            .put(item(json!({"id": "a", "n": 1})), None)
+            .await
+            .unwrap();
+        let stored = store.get(&key, None).await.unwrap().unwrap();
+        assert_eq!(stored["n"], 1);
+
+        let old = store
+            .delete(&key, None, ReturnValues::AllOld)
+            .await
+            .unwrap();
+        assert_eq!(old, Some(stored));
+        assert_eq!(store.get(&key, None).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn writes_only_when_the_condition_holds() {
+        let store = store();
+        let exists = || Condition::Exists(DocumentPath::attr("id"));
+
+        let put = store.put(item(json!({"id": "a"})), Some(exists())).await;
+        assert!(matches!(put, Err(StoreError::ConditionFailed)));
+        assert_eq!(store.get(&Key::new("a", None), None).await.unwrap(), None);
+
+        store.put(item(json!({"id": "a"})), None).await.unwrap();
+        let put = store
+            .put(
+                item(json!({"id": "a"})),
+                Some(Condition::NotExists(DocumentPath::attr("id"))),
+            )
+            .await;
+        assert!(matches!(put, Err(StoreError::ConditionFailed)));
+    }
+
+    #[tokio::test]
+    async fn rejects_items_without_their_key() {
+        let put = store().put(item(json!({"n": 1})), None).await;
+        assert!(matches!(put, Err(StoreError::Validation(_))));
+    }
+
+    #[tokio::test]
+    async fn transactions_write_everything_or_nothing() {
+        let store = store();
+        store
+            .put(item(json!({"id": "a", "n": 1})), None)
+            .await
+            .unwrap();
+        let exists = || Condition::Exists(DocumentPath::attr("id"));
+        let increment = || Update {
+            add: item(json!({"n": 1})),
+            ..Update::default()
+        };
+
+        let canceled = store
+            .transact_write(vec![
+                TransactWrite::Update {
+                    key: Key::new("a", None),
+                    update: increment(),
+                    condition: Some(exists()),
+                },
+                TransactWrite::Put {
+                    item: item(json!({"id": "b"})),
+                    condition: None,
+                },
+                TransactWrite::Check {
+                    key: Key::new("missing", None),
+                    condition: exists(),
+                },
+            ])
+            .await;
+        let Err(StoreError::Canceled(reasons)) = canceled else {
+            panic!("expected a canceled transaction, got {:?}", canceled);
+        };
+        assert!(matches!(
+            reasons.as_slice(),
+            [None, None, Some(StoreError::ConditionFailed)]
+        ));
+        let a = store.get(&Key::new("a", None), None).await.unwrap();
+        assert_eq!(a.unwrap()["n"], 1);
+        assert_eq!(store.get(&Key::new("b", None), None).await.unwrap(), None);
+
+        store
+            .transact_write(vec![
+                TransactWrite::Update {
+                    key: Key::new("a", None),
+                    update: increment(),
+                    condition: Some(exists()),
+                },
+                TransactWrite::Delete {
+                    key: Key::new("a2", None),
+                    condition: None,
+                },
+            ])
+            .await
+            .unwrap();
+        let a = store.get(&Key::new("a", None), None).await.unwrap();
+        assert_eq!(a.unwrap()["n"], 2);
+    }
+
+    #[tokio::test]
+    async fn transactions_write_each_item_once() {
+        let key = || Key::new("a", None);
+        let result = store()
+            .transact_write(vec![
+                TransactWrite::Delete {
+                    key: key(),
+                    condition: None,
+                },
+                TransactWrite::Check {
+                    key: key(),
+                    condition: Condition::Exists(DocumentPath::attr("id")),
+                },
+            ])
+            .await;
+        assert!(matches!(result, Err(StoreError::Validation(_))));
+    }
+
+    fn response() -> RecordedResponse {
+        RecordedResponse {
+            status: 201,
+            headers: vec![],
+            body: "{}".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn unfinished_claims_expire() {
+        let store = MemoryIdempotencyStore::new();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        assert!(store.claim("k", "h", now - 1).await.unwrap().is_none());
+        // The claim above has expired, so the key can be claimed again.
+        assert!(store.claim("k", "h", now + 60).await.unwrap().is_none());
+        let held = store.claim("k", "h", now + 60).await.unwrap().unwrap();
+        assert!(held.response.is_none());
+    }
+
+    #[tokio::test]
+    async fn recording_keeps_the_response_until_its_expiry() {
+        let store = MemoryIdempotencyStore::new();
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+
+        store.claim("k", "h", now - 1).await.unwrap();
+        store.record("k", &response(), now + 60).await.unwrap();
+        let record = store.claim("k", "h", now + 1).await.unwrap().unwrap();
+        assert_eq!(record.response.unwrap().status, 201);
+
+        store.release("k").await.unwrap();
+        assert!(store.claim("k", "h", now + 1).await.unwrap().is_none());
+    }
+
+    async fn orders() -> MemoryStore {
+        let store = MemoryStore::new(KeySchema {
+            pk: "id".into(),
+            sk: Some("sk".into()),
+        });
+        for (id, sk) in [("a", "3"), ("a", "1"), ("b", "1"), ("a", "2"), ("a", "4")] {
+            store
+                .put(item(json!({"id": id, "sk": sk})), None)
+                .await
+                .unwrap();
+        }
+        store
+    }
+
+    fn query(sort: Option<SortCondition>, forward: bool) -> KeyQuery {
+        KeyQuery {
+            index: None,
+            id: "a".into(),
+            sort,
+            forward,
+        }
+    }
+
+    fn sort_keys(page: &Page) -> Vec<&str> {
+        page.items
+            .iter()
+            .map(|item| item["sk"].as_str().unwrap())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn queries_a_partition_in_sort_key_order() {
+        let store = orders().await;
+
+        let page = store
+            .query(query(None, true), PageRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(sort_keys(&page), ["1", "2", "3", "4"]);
+        assert!(page.last_key.is_none());
+
+        let between = SortCondition::Between("2".into(), "3".into());
+        let page = store
+            .query(query(Some(between), false), PageRequest::default())
+            .await
+            .unwrap();
+        assert_eq!(sort_keys(&page), ["3", "2"]);
+    }
+
+    #[tokio::test]
+    async fn resumes_queries_in_either_direction() {
+        let store = orders().await;
+
+        for (forward, expected) in [(true, ["1", "2", "3", "4"]), (false, ["4", "3", "2", "1"])] {
+            let mut read = vec![];
+            let mut start_key = None;
+            loop {
+                let request = PageRequest {
+                    limit: Some(3),
+                    start_key: start_key.take(),
+                    ..PageRequest::default()
+                };
+                let page = store.query(query(None, forward), request).await.unwrap();
+                read.extend(sort_keys(&page).into_iter().map(String::from));
+                match page.last_key {
+                    Some(last_key) => start_key = Some(last_key),
+                    None => break,
+                }
+            }
+            assert_eq!(read, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn queries_need_a_sort_key_for_sort_conditions() {
+        let condition = SortCondition::BeginsWith("a".into());
+        let result = store()
+            .query(query(Some(condition), true), PageRequest::default())
+            .await;
+        assert!(matches!(result, Err(StoreError::Validation(_))));
+    }
+}
@@ -0,0 +1,389 @@
+mod dynamo;
+mod key;
+mod memory;
+mod path;
+
+pub use dynamo::{DynamoIdempotencyStore, DynamoStore};
+pub use key::{Key, KeySchema};
+pub use memory::{MemoryIdempotencyStore, MemoryStore};
+pub use path::{project, DocumentPath, Segment};
+
+use std::cmp::Ordering;
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+pub type Item = Map<String, Value>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum StoreError {
+    #[error("conditional check failed")]
+    ConditionFailed,
+    #[error("conflicting concurrent write: {0}")]
+    Conflict(String),
+    #[error("invalid request: {0}")]
+    Validation(String),
+    #[error("request throttled")]
+    Throttled,
+    /// A transaction did not go through; holds why each of its writes failed,
+    /// if it did.
+    #[error("transaction canceled")]
+    Canceled(Vec<Option<StoreError>>),
+    #[error("failed to convert item: {0}")]
+    Serde(#[from] serde_dynamo::Error),
+    #[error(transparent)]
+    Backend(Box<dyn std::error::Error + Send + Sync>),
+}
+
+/// One page of a scan or query. `last_key` is set when the read stopped early
+/// and holds the key attributes to resume after.
+#[derive(Debug, Default)]
+pub struct Page {
+    pub items: Vec<Item>,
+    pub last_key: Option<Item>,
+}
+
+/// Which page of a scan or query to read, and what to return of it. Like
+/// DynamoDB's, `limit` counts items read, so a page may hold fewer matches of
+/// `filter` and still have a `last_key`. With a `projection`, items only hold
+/// the values at those paths.
+#[derive(Debug, Clone, Default)]
+pub struct PageRequest {
+    pub limit: Option<usize>,
+    pub start_key: Option<Item>,
+    pub filter: Option<Condition>,
+    pub projection: Option<Vec<DocumentPath>>,
+    /// For scans, read only this segment of the table.
+    pub segment: Option<ScanSegment>,
+}
+
+/// One of `total` disjoint parts of a table, which scans can read in
+/// parallel, numbered from zero.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScanSegment {
+    pub segment: u32,
+    pub total: u32,
+}
+
+/// The items of one partition key, optionally narrowed by a condition on
+/// their sort key, in ascending sort key order if `forward`. With an `index`,
+/// the keys are those of the index rather than the table.
+#[derive(Debug, Clone)]
+pub struct KeyQuery {
+    pub index: Option<Index>,
+    pub id: String,
+    pub sort: Option<SortCondition>,
+    pub forward: bool,
+}
+
+/// A global or local secondary index and its key attributes.
+#[derive(Debug, Clone)]
+pub struct Index {
+    pub name: String,
+    pub keys: KeySchema,
+}
+
+/// Condition on the sort key of a query. Sort keys are strings, so these
+/// compare strings.
+#[derive(Debug, Clone)]
+pub enum SortCondition {
+    Compare(Comparison, String),
+    /// Between the two values, inclusive.
+    Between(String, String),
+    BeginsWith(String),
+}
+
+impl SortCondition {
+    pub fn matches(&self, sk: &str) -> bool {
+        match self {
+            SortCondition::Compare(comparison, v) => comparison.holds(sk.cmp(v.as_str())),
+            SortCondition::Between(low, high) => low.as_str() <= sk && sk <= high.as_str(),
+            SortCondition::BeginsWith(prefix) => sk.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+/// Right-hand side of a `SET` action. Paths are read from the item as it was
+/// before the update.
+#[derive(Debug, Clone)]
+pub enum Operand {
+    Value(Value),
+    Path(DocumentPath),
+    /// The list at the path with the given elements appended.
+    Append(DocumentPath, Vec<Value>),
+    /// Like `Append`, but starting from an empty list if the path is missing.
+    AppendOrCreate(DocumentPath, Vec<Value>),
+}
+
+/// Changes applied by `ItemStore::update`. `add` holds numbers to add to
+/// top-level attributes, starting from zero for missing attributes. No two
+/// paths may overlap.
+#[derive(Debug, Clone, Default)]
+pub struct Update {
+    pub set: Vec<(DocumentPath, Operand)>,
+    pub remove: Vec<DocumentPath>,
+    pub add: Item,
+}
+
+impl Update {
+    pub fn is_empty(&self) -> bool {
+        self.set.is_empty() && self.remove.is_empty() && self.add.is_empty()
+    }
+
+    /// Adds the actions of `other` to this update.
+    pub fn extend(&mut self, other: Update) {
+        self.set.extend(other.set);
+        self.remove.extend(other.remove);
+        self.add.extend(other.add);
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = DocumentPath> + '_ {
+        self.set
+            .iter()
+            .map(|(path, _)| path.clone())
+            .chain(self.remove.iter().cloned())
+            .chain(self.add.keys().map(DocumentPath::attr))
+    }
+
+    pub fn has_overlapping_paths(&self) -> bool {
+        let paths: Vec<_> = self.paths().collect();
+        paths
+            .iter()
+            .enumerate()
+            .any(|(i, path)| paths[i + 1..].iter().any(|other| path.overlaps(other)))
+    }
+}
+
+/// One entry of `ItemStore::batch_write`.
+#[derive(Debug, Clone)]
+pub enum Write {
+    Put(Item),
+    Delete(Key),
+}
+
+/// One write of `ItemStore::transact_write`.
+#[derive(Debug, Clone)]
+pub enum TransactWrite {
+    Put {
+        item: Item,
+        condition: Option<Condition>,
+    },
+    Update {
+        key: Key,
+        update: Update,
+        condition: Option<Condition>,
+    },
+    Delete {
+        key: Key,
+        condition: Option<Condition>,
+    },
+    /// Writes nothing, but the transaction only goes through if the item
+    /// meets `condition`.
+    Check { key: Key, condition: Condition },
+}
+
+/// What a write hands back, after DynamoDB's `ReturnValues`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ReturnValues {
+    #[default]
+    None,
+    /// The whole item after the write.
+    AllNew,
+    /// The whole item before the write, if there was one.
+    AllOld,
+    /// The new values of the top-level attributes the write changed.
+    UpdatedNew,
+}
+
+/// Requirement the stored item must meet for a write to go ahead.
+#[derive(Debug, Clone)]
+pub enum Condition {
+    Exists(DocumentPath),
+    NotExists(DocumentPath),
+    Equals(DocumentPath, Value),
+    NotEquals(DocumentPath, Value),
+    Compare(DocumentPath, Comparison, Value),
+    BeginsWith(DocumentPath, String),
+    /// A string containing the value as a substring, or a list or set
+    /// containing it as an element.
+    Contains(DocumentPath, Value),
+    And(Vec<Condition>),
+}
+
+/// Ordering comparisons, which like DynamoDB's only hold between two numbers
+/// or two strings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+}
+
+impl Comparison {
+    fn holds(self, ordering: Ordering) -> bool {
+        match self {
+            Comparison::Less => ordering.is_lt(),
+            Comparison::LessOrEqual => ordering.is_le(),
+            Comparison::Greater => ordering.is_gt(),
+            Comparison::GreaterOrEqual => ordering.is_ge(),
+        }
+    }
+}
+
+/// Orders two numbers or two strings; other values do not compare.
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => match (a.as_i64(), b.as_i64()) {
+            (Some(a), Some(b)) => Some(a.cmp(&b)),
+            _ => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        },
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        _ => None,
+    }
+}
+
+/// Equality where numbers compare by value, so `1` equals `1.0`.
+fn same(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(_), Value::Number(_)) => compare(a, b).is_some_and(Ordering::is_eq),
+        _ => a == b,
+    }
+}
+
+impl Condition {
+    /// Combines `conditions` so that all of them must hold, or `None` if there
+    /// are none.
+    pub fn all(conditions: impl IntoIterator<Item = Condition>) -> Option<Condition> {
+        let mut conditions: Vec<_> = conditions.into_iter().collect();
+        match conditions.len() {
+            0 => None,
+            1 => conditions.pop(),
+            _ => Some(Condition::And(conditions)),
+        }
+    }
+
+    /// Evaluates the condition like DynamoDB: anything but `NotExists` is
+    /// false for a missing attribute.
+    pub fn matches(&self, item: Option<&Item>) -> bool {
+        let get = |path: &DocumentPath| item.and_then(|item| path.get(item));
+        match self {
+            Condition::Exists(path) => get(path).is_some(),
+            Condition::NotExists(path) => get(path).is_none(),
+            Condition::Equals(path, v) => get(path).is_some_and(|current| same(current, v)),
+            Condition::NotEquals(path, v) => get(path).is_some_and(|current| !same(current, v)),
+            Condition::Compare(path, comparison, v) => get(path)
+                .and_then(|current| compare(current, v))
+                .is_some_and(|ordering| comparison.holds(ordering)),
+            Condition::BeginsWith(path, prefix) => get(path)
+                .and_then(Value::as_str)
+                .is_some_and(|current| current.starts_with(prefix.as_str())),
+            Condition::Contains(path, v) => match (get(path), v) {
+                (Some(Value::String(current)), Value::String(v)) => current.contains(v.as_str()),
+                (Some(Value::Array(elements)), v) => elements.iter().any(|e| same(e, v)),
+                _ => false,
+            },
+            Condition::And(conditions) => conditions.iter().all(|c| c.matches(item)),
+        }
+    }
+}
+
+#[async_trait]
+pub trait ItemStore: Send + Sync {
+    /// Writes `item`, replacing any existing item with the same key. Fails
+    /// with `StoreError::ConditionFailed` if `condition` is given and not met.
+    async fn put(&self, item: Item, condition: Option<Condition>) -> Result<(), StoreError>;
+
+    /// Reads the item, or only the values at `projection` if given.
+    async fn get(
+        &self,
+        key: &Key,
+        projection: Option<&[DocumentPath]>,
+    ) -> Result<Option<Item>, StoreError>;
+
+    /// Fetches the items with the given keys, in no particular order. Keys
+    /// without an item are left out, and `keys` must not repeat.
+    async fn batch_get(&self, keys: &[Key]) -> Result<Vec<Item>, StoreError>;
+
+    /// Reads a page of all items, in no particular order.
+    async fn scan(&self, request: PageRequest) -> Result<Page, StoreError>;
+
+    /// Reads a page of the items `query` selects, in sort key order. A sort
+    /// key condition needs a sort key to apply to.
+    async fn query(&self, query: KeyQuery, request: PageRequest) -> Result<Page, StoreError>;
+
+    /// Deletes the item, returning it if `returns` is `AllOld` and it existed;
+    /// other `ReturnValues` return nothing. Fails with
+    /// `StoreError::ConditionFailed` if `condition` is given and not met.
+    async fn delete(
+        &self,
+        key: &Key,
+        condition: Option<Condition>,
+        returns: ReturnValues,
+    ) -> Result<Option<Item>, StoreError>;
+
+    /// Applies `writes` unconditionally and returns their outcomes in the same
+    /// order; each write succeeds or fails on its own. No two writes may be
+    /// for the same key.
+    async fn batch_write(&self, writes: Vec<Write>) -> Vec<Result<(), StoreError>>;
+
+    /// Applies all of `writes` atomically, each to a different item. If any
+    /// of them fails, none is applied and the error is
+    /// `StoreError::Canceled` with the reason for each write.
+    async fn transact_write(&self, writes: Vec<TransactWrite>) -> Result<(), StoreError>;
+
+    /// Applies `update` to the item, creating it if it does not exist, and
+    /// returns what `returns` asks for. Fails with
+    /// `StoreError::ConditionFailed` if `condition` is given and not met.
+    async fn update(
+        &self,
+        key: &Key,
+        update: Update,
+        condition: Option<Condition>,
+        returns: ReturnValues,
+    ) -> Result<Option<Item>, StoreError>;
+}
+
+/// A response kept for replaying to repeats of an idempotent request.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+/// What is stored for an idempotency key: the hash of the request that first
+/// used it, and its response once there is one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IdempotencyRecord {
+    pub request_hash: String,
+    pub response: Option<RecordedResponse>,
+}
+
+#[async_trait]
+pub trait IdempotencyStore: Send + Sync {
+    /// Claims `key` for a request with the given hash until `expires_at`
+    /// (seconds since the epoch), which only needs to outlast the request. If
+    /// an unexpired record already holds the key, that record is returned
+    /// instead.
+    async fn claim(
+        &self,
+        key: &str,
+        request_hash: &str,
+        expires_at: u64,
+    ) -> Result<Option<IdempotencyRecord>, StoreError>;
+
+    /// Stores the response of the request that claimed `key`, to be replayed
+    /// until `expires_at`.
+    async fn record(
+        &self,
+        key: &str,
+        response: &RecordedResponse,
+        expires_at: u64,
+    ) -> Result<(), StoreError>;
+
+    /// Gives up the claim on `key`, so that the request can be retried.
+    async fn release(&self, key: &str) -> Result<(), StoreError>;
+}
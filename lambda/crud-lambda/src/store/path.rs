@@ -0,0 +1,346 @@
+use std::collections::BTreeMap;
+
+use serde_json::Value;
+
+use super::{Item, StoreError};
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Segment {
+    Attr(String),
+    Index(usize),
+}
+
+/// Location of a value inside an item, such as `meta.tags[0]`. Always starts
+/// with a top-level attribute name.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DocumentPath {
+    attr: String,
+    rest: Vec<Segment>,
+}
+
+fn invalid_path() -> StoreError {
+    StoreError::Validation(
+        "The document path provided in the update expression is invalid for update".into(),
+    )
+}
+
+impl DocumentPath {
+    /// Parses a path written like `meta.tags[0]`: attribute names separated by
+    /// dots, each optionally followed by list indices.
+    pub fn parse(path: &str) -> Option<Self> {
+        let mut document_path: Option<DocumentPath> = None;
+
+        for part in path.split('.') {
+            let (name, mut indices) = part.split_at(part.find('[').unwrap_or(part.len()));
+            if name.is_empty() {
+                return None;
+            }
+            let mut next = match document_path {
+                None => DocumentPath::attr(name),
+                Some(path) => path.child(name),
+            };
+            while let Some(rest) = indices.strip_prefix('[') {
+                let (index, rest) = rest.split_once(']')?;
+                next = next.index(index.parse().ok()?);
+                indices = rest;
+            }
+            if !indices.is_empty() {
+                return None;
+            }
+            document_path = Some(next);
+        }
+
+        document_path
+    }
+
+    pub fn attr(name: impl Into<String>) -> Self {
+        Self {
+            attr: name.into(),
+            rest: vec![],
+        }
+    }
+
+    pub fn child(&self, name: impl Into<String>) -> Self {
+        let mut path = self.clone();
+        path.rest.push(Segment::Attr(name.into()));
+        path
+    }
+
+    pub fn index(&self, index: usize) -> Self {
+        let mut path = self.clone();
+        path.rest.push(Segment::Index(index));
+        path
+    }
+
+    /// The top-level attribute the path starts at.
+    pub fn top(&self) -> &str {
+        &self.attr
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.rest
+    }
+
+    /// Whether one path is a prefix of the other, which DynamoDB rejects
+    /// within a single update expression.
+    pub fn overlaps(&self, other: &DocumentPath) -> bool {
+        self.attr == other.attr && self.rest.iter().zip(&other.rest).all(|(a, b)| a == b)
+    }
+
+    pub fn get<'a>(&self, item: &'a Item) -> Option<&'a Value> {
+        self.rest
+            .iter()
+            .try_fold(item.get(&self.attr)?, |value, segment| match segment {
+                Segment::Attr(name) => value.as_object()?.get(name),
+                Segment::Index(index) => value.as_array()?.get(*index),
+            })
+    }
+
+    /// Splits off the last segment, returning the parent value it lives in.
+    fn parent_mut<'a>(&self, item: &'a mut Item) -> Option<(&'a mut Value, &Segment)> {
+        let (last, parents) = self.rest.split_last()?;
+        let parent = parents
+            .iter()
+            .try_fold(item.get_mut(&self.attr)?, |value, segment| match segment {
+                Segment::Attr(name) => value.as_object_mut()?.get_mut(name),
+                Segment::Index(index) => value.as_array_mut()?.get_mut(*index),
+            })?;
+        Some((parent, last))
+    }
+
+    /// Assigns `value` the way a DynamoDB `SET` does: the parent must already
+    /// exist, and list indices past the end append.
+    pub fn set(&self, item: &mut Item, value: Value) -> Result<(), StoreError> {
+        let Some((parent, last)) = self.parent_mut(item) else {
+            if self.rest.is_empty() {
+                item.insert(self.attr.clone(), value);
+                return Ok(());
+            }
+            return Err(invalid_path());
+        };
+
+        match (parent, last) {
+            (Value::Object(map), Segment::Attr(name)) => {
+                map.insert(name.clone(), value);
+            }
+            (Value::Array(list), Segment::Index(index)) if *index < list.len() => {
+                list[*index] = value;
+            }
+            (Value::Array(list), Segment::Index(_)) => list.push(value),
+            _ => return Err(invalid_path()),
+        }
+        Ok(())
+    }
+
+    /// Removes the value the way a DynamoDB `REMOVE` does: missing leaves are
+    /// ignored, but the parent must exist.
+    pub fn remove(&self, item: &mut Item) -> Result<(), StoreError> {
+        let Some((parent, last)) = self.parent_mut(item) else {
+            if self.rest.is_empty() {
+                item.remove(&self.attr);
+                return Ok(());
+            }
+            return Err(invalid_path());
+        };
+
+        match (parent, last) {
+            (Value::Object(map), Segment::Attr(name)) => {
+                map.remove(name);
+            }
+            (Value::Array(list), Segment::Index(index)) => {
+                if *index < list.len() {
+                    list.remove(*index);
+                }
+            }
+            _ => return Err(invalid_path()),
+        }
+        Ok(())
+    }
+}
+
+/// Tree of projected values, mirroring the paths they were found at.
+enum Projected {
+    Leaf(Value),
+    Branch(BTreeMap<Segment, Projected>),
+}
+
+impl Projected {
+    fn into_value(self) -> Value {
+        match self {
+            Projected::Leaf(value) => value,
+            Projected::Branch(children) => {
+                if children
+                    .keys()
+                    .all(|segment| matches!(segment, Segment::Index(_)))
+                {
+                    Value::Array(children.into_values().map(Projected::into_value).collect())
+                } else {
+                    Value::Object(
+                        children
+                            .into_iter()
+                            .filter_map(|(segment, child)| match segment {
+                                Segment::Attr(name) => Some((name, child.into_value())),
+                                Segment::Index(_) => None,
+                            })
+                            .collect(),
+                    )
+                }
+            }
+        }
+    }
+}
+
+/// Copies the values at `paths` out of `item` the way a DynamoDB projection
+/// does: missing paths are left out, and list elements picked by index are
+/// kept in index order without gaps. No two paths may overlap.
+pub fn project(item: &Item, paths: &[DocumentPath]) -> Item {
+    let mut root = BTreeMap::new();
+
+    for path in paths {
+        let Some(value) = path.get(item) else {
+            continue;
+        };
+        let mut segments = std::iter::once(Segment::Attr(path.attr.clone()))
+            .chain(path.rest.iter().cloned())
+            .peekable();
+        let mut children = &mut root;
+        while let Some(segment) = segments.next() {
+            if segments.peek().is_none() {
+                children.insert(segment, Projected::Leaf(value.clone()));
+                break;
+            }
+            let child = children
+                .entry(segment)
+                .or_insert_with(|| Projected::Branch(BTreeMap::new()));
+            children = match child {
+                Projected::Branch(grandchildren) => grandchildren,
+                Projected::Leaf(_) => break,
+            };
+        }
+    }
+
+    root.into_iter()
+        .filter_map(|(segment, projected)| match segment {
+            Segment::Attr(name) => Some((name, projected.into_value())),
+            Segment::Index(_) => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn item(value: Value) -> Item {
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn parses_paths() {
+        assert_eq!(
+            DocumentPath::parse("meta.tags[0][2].name"),
+            Some(
+                DocumentPath::attr("meta")
+                    .child("tags")
+                    .index(0)
+                    .index(2)
+                    .child("name")
+            )
+        );
+        for invalid in ["", "a..b", ".a", "a[x]", "a[0]b", "a[0", "[0]"] {
+            assert_eq!(DocumentPath::parse(invalid), None, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn sets_like_dynamodb() {
+        let mut item = item(json!({"meta": {"tags": ["a", "b"]}}));
+
+        DocumentPath::parse("meta.owner")
+            .unwrap()
+            .set(&mut item, json!("me"))
+            .unwrap();
+        DocumentPath::parse("meta.tags[1]")
+            .unwrap()
+            .set(&mut item, json!("c"))
+            .unwrap();
+        DocumentPath::parse("meta.tags[9]")
+            .unwrap()
+            .set(&mut item, json!("d"))
+            .unwrap();
+        assert_eq!(
+            Value::Object(item.clone()),
+            json!({"meta": {"owner": "me", "tags": ["a", "c", "d"]}})
+        );
+
+        for invalid in ["missing.child", "meta.tags.name", "meta.owner[0]"] {
+            let path = DocumentPath::parse(invalid).unwrap();
+            assert!(
+                matches!(
+                    path.set(&mut item, json!(1)),
+                    Err(StoreError::Validation(_))
+                ),
+                "{}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn removes_like_dynamodb() {
+        let mut item = item(json!({"a": 1, "meta": {"tags": ["a", "b"], "x": 1}}));
+
+        for path in [
+            "a",
+            "meta.x",
+            "meta.tags[0]",
+            "meta.missing",
+            "meta.tags[5]",
+        ] {
+            DocumentPath::parse(path)
+                .unwrap()
+                .remove(&mut item)
+                .unwrap();
+        }
+        assert_eq!(
+            Value::Object(item.clone()),
+            json!({"meta": {"tags": ["b"]}})
+        );
+
+        let path = DocumentPath::parse("missing.child").unwrap();
+        assert!(matches!(
+            path.remove(&mut item),
+            Err(StoreError::Validation(_))
+        ));
+    }
+
+    #[test]
+    fn overlapping_paths_share_a_prefix() {
+        let parse = |path| DocumentPath::parse(path).unwrap();
+        assert!(parse("a.b").overlaps(&parse("a")));
+        assert!(parse("a[0]").overlaps(&parse("a[0].b")));
+        assert!(!parse("a.b").overlaps(&parse("a.c")));
+        assert!(!parse("a[0]").overlaps(&parse("a[1]")));
+    }
+
+    #[test]
+    fn projects_like_dynamodb() {
+        let item = item(json!({
+            "id": "a",
+            "meta": {"color": "red", "size": 2},
+            "tags": ["x", "y", "z"],
+            "other": 1,
+        }));
+        let paths: Vec<_> = ["id", "meta.color", "tags[2]", "tags[0]", "missing.path"]
+            .into_iter()
+            .map(|path| DocumentPath::parse(path).unwrap())
+            .collect();
+
+        assert_eq!(
+            Value::Object(project(&item, &paths)),
+            json!({"id": "a", "meta": {"color": "red"}, "tags": ["x", "z"]})
+        );
+    }
+}
@@ -0,0 +1,1190 @@
+//! Tests driving the routes end to end against the in-memory backend.
+
+use std::{
+    num::NonZeroUsize,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use axum::{
+    async_trait,
+    body::{to_bytes, Body},
+    http::{header::CONTENT_TYPE, HeaderMap, Method, Request, StatusCode},
+    Router,
+};
+use serde_json::{json, Value};
+use tower::ServiceExt;
+
+use crate::{
+    config::{Backend, Config, DynamoConfig},
+    router,
+    store::{
+        Condition, DocumentPath, Index, Item, ItemStore, Key, KeyQuery, KeySchema,
+        MemoryIdempotencyStore, MemoryStore, Page, PageRequest, ReturnValues, StoreError,
+        TransactWrite, Update, Write,
+    },
+    AppState,
+};
+
+fn config() -> Config {
+    Config {
+        table_name: "items".into(),
+        pk: "itemId".into(),
+        sk: None,
+        indexes: vec![],
+        backend: Backend::Memory,
+        legacy_routes: false,
+        idempotency_table: None,
+        idempotency_ttl: Duration::from_secs(60),
+        idempotency_lock: Duration::from_secs(10),
+        scan_concurrency: NonZeroUsize::new(4).unwrap(),
+        scan_time_budget: Duration::from_secs(5),
+        scan_time_margin: Duration::from_secs(1),
+        local_addr: None,
+        response_streaming: false,
+        dynamodb: DynamoConfig::default(),
+    }
+}
+
+fn app(config: Config) -> Router {
+    router(&config).with_state(AppState {
+        store: Arc::new(MemoryStore::new(config.keys())),
+        idempotency: Some(Arc::new(MemoryIdempotencyStore::new())),
+        config: Arc::new(config),
+    })
+}
+
+struct Response {
+    status: StatusCode,
+    headers: HeaderMap,
+    body: Value,
+}
+
+impl Response {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(|value| value.to_str().unwrap())
+    }
+}
+
+/// Sends one request to `app`, with a JSON `body` if given, and reads the
+/// response body as JSON, or as `null` if it is empty.
+async fn send(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    headers: &[(&str, &str)],
+    body: Option<Value>,
+) -> Response {
+    let mut request = Request::builder().method(method).uri(uri);
+    for (name, value) in headers {
+        request = request.header(*name, *value);
+    }
+    let body = match body {
+        Some(body) => {
+            request = request.header(CONTENT_TYPE, "application/json");
+            Body::from(body.to_string())
+        }
+        None => Body::empty(),
+    };
+
+    let response = app
+        .clone()
+        .oneshot(request.body(body).unwrap())
+        .await
+        .unwrap();
+    let (parts, body) = response.into_parts();
+    let bytes = to_bytes(body, usize::MAX).await.unwrap();
+    Response {
+        status: parts.status,
+        headers: parts.headers,
+        body: if bytes.is_empty() {
+            Value::Null
+        } else {
+            serde_json::from_slice(&bytes).unwrap()
+        },
+    }
+}
+
+async fn get(app: &Router, uri: &str) -> Response {
+    send(app, Method::GET, uri, &[], None).await
+}
+
+/// The target of the `rel="next"` link of a page, if there is one.
+fn next_page(response: &Response) -> Option<String> {
+    let link = response.header("link")?;
+    let (target, _) = link.strip_prefix('<')?.split_once('>')?;
+    Some(target.to_string())
+}
+
+/// Follows the `next` links from `uri`, collecting the items of every page.
+async fn get_pages(app: &Router, uri: &str) -> Vec<Vec<Value>> {
+    let mut pages = vec![];
+    let mut next = Some(uri.to_string());
+    while let Some(uri) = next {
+        let response = get(app, &uri).await;
+        assert_eq!(response.status, StatusCode::OK);
+        pages.push(response.body.as_array().unwrap().clone());
+        next = next_page(&response);
+    }
+    pages
+}
+
+/// Creates an item and returns its id.
+async fn create(app: &Router, item: Value) -> String {
+    let response = send(app, Method::POST, "/items", &[], Some(item)).await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    response.body["itemId"].as_str().unwrap().to_string()
+}
+
+/// The in-memory store, counting the items its scans hand out, and with
+/// another client moving the item on to its next version just before each of
+/// the first `races` updates.
+struct SpyStore {
+    inner: MemoryStore,
+    scanned: AtomicUsize,
+    races: AtomicUsize,
+}
+
+#[async_trait]
+impl ItemStore for SpyStore {
+    async fn put(&self, item: Item, condition: Option<Condition>) -> Result<(), StoreError> {
+        self.inner.put(item, condition).await
+    }
+
+    async fn get(
+        &self,
+        key: &Key,
+        projection: Option<&[DocumentPath]>,
+    ) -> Result<Option<Item>, StoreError> {
+        self.inner.get(key, projection).await
+    }
+
+    async fn batch_get(&self, keys: &[Key]) -> Result<Vec<Item>, StoreError> {
+        self.inner.batch_get(keys).await
+    }
+
+    async fn scan(&self, request: PageRequest) -> Result<Page, StoreError> {
+        let page = self.inner.scan(request).await?;
+        self.scanned.fetch_add(page.items.len(), Ordering::SeqCst);
+        Ok(page)
+    }
+
+    async fn query(&self, query: KeyQuery, request: PageRequest) -> Result<Page, StoreError> {
+        self.inner.query(query, request).await
+    }
+
+    async fn delete(
+        &self,
+        key: &Key,
+        condition: Option<Condition>,
+        returns: ReturnValues,
+    ) -> Result<Option<Item>, StoreError> {
+        self.inner.delete(key, condition, returns).await
+    }
+
+    async fn batch_write(&self, writes: Vec<Write>) -> Vec<Result<(), StoreError>> {
+        self.inner.batch_write(writes).await
+    }
+
+    async fn transact_write(&self, writes: Vec<TransactWrite>) -> Result<(), StoreError> {
+        self.inner.transact_write(writes).await
+    }
+
+    async fn update(
+        &self,
+        key: &Key,
+        update: Update,
+        condition: Option<Condition>,
+        returns: ReturnValues,
+    ) -> Result<Option<Item>, StoreError> {
+        let raced = self
+            .races
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |races| {
+                races.checked_sub(1)
+            })
+            .is_ok();
+        if raced {
+            let mut other = Update::default();
+            other.add.insert("_version".into(), json!(1));
+            self.inner
+                .update(key, other, None, ReturnValues::None)
+                .await?;
+        }
+        self.inner.update(key, update, condition, returns).await
+    }
+}
+
+fn spied_app(config: Config) -> (Router, Arc<SpyStore>) {
+    let store = Arc::new(SpyStore {
+        inner: MemoryStore::new(config.keys()),
+        scanned: AtomicUsize::new(0),
+        races: AtomicUsize::new(0),
+    });
+    let app = router(&config).with_state(AppState {
+        store: store.clone(),
+        idempotency: None,
+        config: Arc::new(config),
+    });
+    (app, store)
+}
+
+#[tokio::test]
+async fn creates_reads_and_deletes_items() {
+    let app = app(config());
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/items",
+        &[],
+        Some(json!({"name": "first"})),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    let id = response.body["itemId"].as_str().unwrap();
+    assert_eq!(
+        response.header("location"),
+        Some(&*format!("/items/{}", id))
+    );
+    let response = get(&app, &format!("/items/{}", id)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["name"], "first");
+
+    create(&app, json!({"name": "second"})).await;
+    let response = get(&app, "/items").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body.as_array().unwrap().len(), 2);
+
+    let uri = format!("/items/{}", id);
+    let response = send(&app, Method::DELETE, &uri, &[], None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(get(&app, &uri).await.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn rejects_bodies_that_are_not_objects() {
+    let app = app(config());
+
+    let response = send(&app, Method::POST, "/items", &[], Some(json!([1, 2]))).await;
+    assert!(response.status.is_client_error());
+    assert_eq!(get(&app, "/items").await.body, json!([]));
+}
+
+#[tokio::test]
+async fn pages_through_items_with_cursors() {
+    let app = app(config());
+    for n in 0..5 {
+        create(&app, json!({"n": n})).await;
+    }
+
+    let pages = get_pages(&app, "/items?limit=2").await;
+    let sizes: Vec<_> = pages.iter().map(Vec::len).collect();
+    assert_eq!(sizes, [2, 2, 1]);
+    let mut ns: Vec<_> = pages
+        .concat()
+        .iter()
+        .map(|item| item["n"].clone())
+        .collect();
+    ns.sort_by_key(|n| n.as_i64());
+    assert_eq!(ns, [0, 1, 2, 3, 4]);
+
+    let response = get(&app, "/items?cursor=garbage").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    assert_eq!(
+        get(&app, "/items?limit=0").await.status,
+        StatusCode::BAD_REQUEST
+    );
+}
+
+#[tokio::test]
+async fn parallel_scans_stop_at_the_limit() {
+    let (app, store) = spied_app(config());
+    for n in 0..32 {
+        create(&app, json!({"n": n})).await;
+    }
+
+    // Every item read makes it into a response, so none is read twice.
+    let pages = get_pages(&app, "/items?segments=4&limit=2").await;
+    assert!(pages.iter().all(|page| page.len() <= 2));
+    assert_eq!(store.scanned.load(Ordering::SeqCst), 32);
+    let mut ns: Vec<_> = pages
+        .concat()
+        .iter()
+        .map(|item| item["n"].as_i64().unwrap())
+        .collect();
+    ns.sort();
+    assert_eq!(ns, (0..32).collect::<Vec<_>>());
+
+    let pages = get_pages(&app, "/items?segments=3&limit=10").await;
+    assert!(pages.iter().all(|page| page.len() <= 10));
+    assert_eq!(pages.concat().len(), 32);
+    assert_eq!(store.scanned.load(Ordering::SeqCst), 64);
+
+    let pages = get_pages(&app, "/items?segments=4").await;
+    assert_eq!(pages.concat().len(), 32);
+}
+
+#[tokio::test]
+async fn answers_errors_with_problem_documents() {
+    let app = app(config());
+
+    let response = get(&app, "/items/missing").await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        response.header("content-type"),
+        Some("application/problem+json")
+    );
+    assert_eq!(response.body["status"], 404);
+    assert_eq!(response.body["detail"], "item not found");
+
+    let request = Request::post("/items")
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from("{not json"))
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(response.headers()[CONTENT_TYPE], "application/problem+json");
+}
+
+#[tokio::test]
+async fn leaves_out_the_created_item_when_asked() {
+    let app = app(config());
+
+    let prefer = [("prefer", "return=minimal")];
+    let response = send(&app, Method::POST, "/items", &prefer, Some(json!({"n": 1}))).await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert_eq!(
+        response.header("preference-applied"),
+        Some("return=minimal")
+    );
+    assert_eq!(response.header("etag"), Some("\"1\""));
+    assert_eq!(response.body, Value::Null);
+
+    let location = response.header("location").unwrap();
+    let response = get(&app, location).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["n"], 1);
+}
+
+#[tokio::test]
+async fn serves_legacy_routes_as_deprecated() {
+    let app = app(Config {
+        legacy_routes: true,
+        ..config()
+    });
+    let id = create(&app, json!({"n": 1})).await;
+
+    let response = get(&app, &format!("/{}", id)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("deprecation"), Some("true"));
+    assert_eq!(response.body["n"], 1);
+
+    let response = get(&app, &format!("/items/{}", id)).await;
+    assert_eq!(response.header("deprecation"), None);
+}
+
+#[tokio::test]
+async fn leaves_legacy_routes_out_by_default() {
+    let app = app(config());
+    let id = create(&app, json!({"n": 1})).await;
+
+    let response = get(&app, &format!("/{}", id)).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn writes_only_match_the_current_version() {
+    let app = app(config());
+    let id = create(&app, json!({"n": 1})).await;
+    let uri = format!("/items/{}", id);
+
+    let stale = [("if-match", "\"2\"")];
+    let response = send(&app, Method::PUT, &uri, &stale, Some(json!({"n": 2}))).await;
+    assert_eq!(response.status, StatusCode::PRECONDITION_FAILED);
+    let response = send(&app, Method::DELETE, &uri, &stale, None).await;
+    assert_eq!(response.status, StatusCode::PRECONDITION_FAILED);
+
+    let current = [("if-match", "\"1\"")];
+    let response = send(&app, Method::PUT, &uri, &current, Some(json!({"n": 2}))).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("etag"), Some("\"2\""));
+    let response = send(&app, Method::DELETE, &uri, &current, None).await;
+    assert_eq!(response.status, StatusCode::PRECONDITION_FAILED);
+
+    let response = send(
+        &app,
+        Method::PUT,
+        "/items/missing",
+        &[("if-match", "*")],
+        Some(json!({})),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::PRECONDITION_FAILED);
+    let response = send(&app, Method::DELETE, &uri, &[("if-match", "\"2\"")], None).await;
+    assert_eq!(response.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn answers_conditional_gets_with_not_modified() {
+    let app = app(config());
+    let id = create(&app, json!({"n": 1})).await;
+    let uri = format!("/items/{}", id);
+
+    let response = get(&app, &uri).await;
+    let last_modified = response.header("last-modified").unwrap().to_string();
+
+    let response = send(&app, Method::GET, &uri, &[("if-none-match", "\"1\"")], None).await;
+    assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+    assert_eq!(response.header("etag"), Some("\"1\""));
+    assert_eq!(response.body, Value::Null);
+
+    let since = [("if-modified-since", last_modified.as_str())];
+    let response = send(&app, Method::GET, &uri, &since, None).await;
+    assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+
+    send(&app, Method::PUT, &uri, &[], Some(json!({"n": 2}))).await;
+    let response = send(&app, Method::GET, &uri, &[("if-none-match", "\"1\"")], None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["n"], 2);
+}
+
+#[tokio::test]
+async fn patch_does_not_create_items_unless_asked() {
+    let app = app(config());
+
+    let response = send(
+        &app,
+        Method::PATCH,
+        "/items/new",
+        &[],
+        Some(json!({"n": 1})),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+    assert_eq!(get(&app, "/items/new").await.status, StatusCode::NOT_FOUND);
+
+    let uri = "/items/new?upsert=true";
+    let response = send(&app, Method::PATCH, uri, &[], Some(json!({"n": 1}))).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = get(&app, "/items/new").await;
+    assert_eq!(response.body["n"], 1);
+    assert_eq!(response.body["itemId"], "new");
+}
+
+#[tokio::test]
+async fn put_creates_or_replaces_the_whole_item() {
+    let app = app(config());
+
+    let body = json!({"a": 1, "b": 2});
+    let response = send(&app, Method::PUT, "/items/x", &[], Some(body)).await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert_eq!(response.header("location"), Some("/items/x"));
+    assert_eq!(response.header("etag"), Some("\"1\""));
+
+    let response = send(&app, Method::PUT, "/items/x", &[], Some(json!({"a": 3}))).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.header("etag"), Some("\"2\""));
+    let item = get(&app, "/items/x").await.body;
+    assert_eq!(item["a"], 3);
+    assert_eq!(item.get("b"), None);
+    assert_eq!(item["itemId"], "x");
+
+    // The path names the item, whatever the body says.
+    let body = json!({"itemId": "y", "a": 4});
+    send(&app, Method::PUT, "/items/x", &[], Some(body)).await;
+    assert_eq!(get(&app, "/items/x").await.body["a"], 4);
+    assert_eq!(get(&app, "/items/y").await.status, StatusCode::NOT_FOUND);
+
+    let create_only = [("if-none-match", "*")];
+    let response = send(&app, Method::PUT, "/items/x", &create_only, Some(json!({}))).await;
+    assert_eq!(response.status, StatusCode::PRECONDITION_FAILED);
+}
+
+#[tokio::test]
+async fn merge_patches_nested_objects() {
+    let app = app(config());
+    let body = json!({"meta": {"a": 1, "b": 2}, "keep": true});
+    send(&app, Method::PUT, "/items/x", &[], Some(body)).await;
+
+    let patch = json!({"meta": {"a": null, "c": 3}});
+    let response = send(&app, Method::PATCH, "/items/x", &[], Some(patch)).await;
+    assert_eq!(response.status, StatusCode::OK);
+
+    let item = get(&app, "/items/x").await.body;
+    assert_eq!(item["meta"], json!({"b": 2, "c": 3}));
+    assert_eq!(item["keep"], true);
+    assert_eq!(item["_version"], 2);
+}
+
+#[tokio::test]
+async fn applies_json_patches() {
+    let app = app(config());
+    let body = json!({"tags": ["a"], "status": "draft", "n": 1});
+    send(&app, Method::PUT, "/items/x", &[], Some(body)).await;
+
+    let patch = |operations: Value| {
+        Request::patch("/items/x")
+            .header(CONTENT_TYPE, "application/json-patch+json")
+            .body(Body::from(operations.to_string()))
+            .unwrap()
+    };
+    let response = app
+        .clone()
+        .oneshot(patch(json!([
+            {"op": "test", "path": "/status", "value": "draft"},
+            {"op": "replace", "path": "/status", "value": "published"},
+            {"op": "add", "path": "/tags/-", "value": "b"},
+            {"op": "remove", "path": "/n"},
+        ])))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let item = get(&app, "/items/x").await.body;
+    assert_eq!(item["status"], "published");
+    assert_eq!(item["tags"], json!(["a", "b"]));
+    assert_eq!(item.get("n"), None);
+
+    // A failed test leaves the item as it was.
+    let response = app
+        .clone()
+        .oneshot(patch(json!([
+            {"op": "test", "path": "/status", "value": "draft"},
+            {"op": "replace", "path": "/status", "value": "archived"},
+        ])))
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+    assert_eq!(get(&app, "/items/x").await.body["status"], "published");
+}
+
+#[tokio::test]
+async fn update_operators_work_on_stored_lists() {
+    let app = app(config());
+    let body = json!({"stock": 5, "tags": ["a", "b"], "log": []});
+    send(&app, Method::PUT, "/items/x", &[], Some(body)).await;
+
+    let patch = json!({
+        "$inc": {"stock": -1},
+        "$append": {"log": "sold"},
+        "$addToSet": {"tags": ["b", "c"]},
+    });
+    let response = send(&app, Method::PATCH, "/items/x", &[], Some(patch)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["stock"], 4);
+    assert_eq!(response.body["tags"], json!(["a", "b", "c"]));
+
+    // Reading an item and writing it back keeps its sets usable.
+    let item = get(&app, "/items/x").await.body;
+    send(&app, Method::PUT, "/items/x", &[], Some(item)).await;
+    let patch = json!({"$removeFromSet": {"tags": ["a", "b", "c"]}});
+    let response = send(&app, Method::PATCH, "/items/x", &[], Some(patch)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["tags"], json!([]));
+
+    let patch = json!({"$addToSet": {"stock": 1}});
+    let response = send(&app, Method::PATCH, "/items/x", &[], Some(patch)).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    let patch = json!({"$inc": {"stock": 1}});
+    let response = send(&app, Method::PATCH, "/items/missing", &[], Some(patch)).await;
+    assert_eq!(response.status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn applies_set_operators_again_after_races() {
+    let (app, store) = spied_app(config());
+    let body = json!({"stock": 0, "tags": ["a"]});
+    send(&app, Method::PUT, "/items/x", &[], Some(body)).await;
+
+    store.races.store(2, Ordering::SeqCst);
+    let patch = json!({"$inc": {"stock": 1}, "$addToSet": {"tags": "b"}});
+    let response = send(&app, Method::PATCH, "/items/x", &[], Some(patch)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["stock"], 1);
+    assert_eq!(response.body["tags"], json!(["a", "b"]));
+    assert_eq!(response.body["_version"], 4);
+
+    // Operators that do not read the item need no retrying.
+    store.races.store(1, Ordering::SeqCst);
+    let patch = json!({"$inc": {"stock": 1}});
+    let response = send(&app, Method::PATCH, "/items/x", &[], Some(patch)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["stock"], 2);
+
+    store.races.store(usize::MAX, Ordering::SeqCst);
+    let patch = json!({"$removeFromSet": {"tags": "a"}});
+    let response = send(&app, Method::PATCH, "/items/x", &[], Some(patch)).await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    assert_eq!(
+        response.body["detail"],
+        "item changed while the patch was applied"
+    );
+
+    // With If-Match the client asked for a version, which a race makes stale.
+    store.races.store(1, Ordering::SeqCst);
+    let version = get(&app, "/items/x").await.body["_version"].to_string();
+    let headers = [("if-match", format!("\"{}\"", version))];
+    let headers: Vec<_> = headers.iter().map(|(k, v)| (*k, v.as_str())).collect();
+    let patch = json!({"$addToSet": {"tags": "c"}});
+    let response = send(&app, Method::PATCH, "/items/x", &headers, Some(patch)).await;
+    assert_eq!(response.status, StatusCode::PRECONDITION_FAILED);
+}
+
+#[tokio::test]
+async fn returns_what_writes_are_asked_to() {
+    let app = app(config());
+    let body = json!({"a": 1, "b": 2});
+    send(&app, Method::PUT, "/items/x", &[], Some(body)).await;
+
+    let patch = || Some(json!({"a": 10}));
+    let response = send(&app, Method::PATCH, "/items/x", &[], patch()).await;
+    assert_eq!(
+        response.body,
+        json!({"a": 10, "_version": 2, "_updated_at": response.body["_updated_at"]})
+    );
+
+    let uri = "/items/x?return=all_new";
+    let response = send(&app, Method::PATCH, uri, &[], patch()).await;
+    assert_eq!(response.body["b"], 2);
+    assert_eq!(response.header("etag"), Some("\"3\""));
+
+    let uri = "/items/x?return=all_old";
+    let response = send(&app, Method::PATCH, uri, &[], patch()).await;
+    assert_eq!(response.body["_version"], 3);
+    assert_eq!(response.header("etag"), None);
+
+    let uri = "/items/x?return=none";
+    let response = send(&app, Method::PATCH, uri, &[], patch()).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, Value::Null);
+
+    let response = send(&app, Method::DELETE, "/items/x?return=all_new", &[], None).await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+    let response = send(&app, Method::DELETE, "/items/x?return=all_old", &[], None).await;
+    assert_eq!(response.body["_version"], 5);
+    let response = send(&app, Method::DELETE, "/items/x", &[], None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, Value::Null);
+}
+
+fn sort_key_config() -> Config {
+    Config {
+        sk: Some("sk".into()),
+        ..config()
+    }
+}
+
+#[tokio::test]
+async fn batch_gets_items_and_lists_missing_ones() {
+    let app = app(config());
+    let a = create(&app, json!({"n": 1})).await;
+    let b = create(&app, json!({"n": 2})).await;
+
+    let uri = format!("/items?ids={},missing,{},{}", b, a, b);
+    let response = get(&app, &uri).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let ns: Vec<_> = response.body["items"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|item| item["n"].clone())
+        .collect();
+    assert_eq!(ns, [2, 1]);
+    assert_eq!(response.body["missing"], json!(["missing"]));
+
+    // Filters leave items out without making them missing.
+    let response = get(&app, &format!("/items?ids={},{}&n[gt]=1&fields=n", a, b)).await;
+    assert_eq!(response.body["items"], json!([{"itemId": b, "n": 2}]));
+    assert_eq!(response.body["missing"], json!([]));
+
+    for uri in ["/items?ids=", "/items?ids=a,", "/items?ids=a&limit=1"] {
+        assert_eq!(
+            get(&app, uri).await.status,
+            StatusCode::BAD_REQUEST,
+            "{}",
+            uri
+        );
+    }
+}
+
+#[tokio::test]
+async fn batch_gets_by_both_keys() {
+    let app = app(sort_key_config());
+    let body = json!({"itemId": "c", "sk": "1", "n": 1});
+    send(&app, Method::POST, "/items", &[], Some(body)).await;
+
+    let body = json!({"itemId": "d,1", "sk": "a/b", "n": 2});
+    send(&app, Method::POST, "/items", &[], Some(body)).await;
+
+    let response = get(&app, "/items?ids=c/1,c/2,d%2C1/a%2Fb").await;
+    assert_eq!(response.body["items"][0]["n"], 1);
+    assert_eq!(response.body["items"][1]["n"], 2);
+    assert_eq!(response.body["missing"], json!([{"id": "c", "sk": "2"}]));
+
+    let response = get(&app, "/items?ids=c").await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn bulk_routes_leave_every_id_to_items() {
+    let app = app(config());
+
+    for id in ["batch", "batch-get", "export"] {
+        let uri = format!("/items/{}", id);
+        let response = send(&app, Method::PUT, &uri, &[], Some(json!({"n": 1}))).await;
+        assert_eq!(response.status, StatusCode::CREATED, "{}", id);
+        let response = get(&app, &uri).await;
+        assert_eq!(response.body["itemId"], id);
+    }
+}
+
+/// Sends a `GET` asking for NDJSON and reads the items off its lines.
+async fn get_ndjson(app: &Router, uri: &str, accept: &str) -> (StatusCode, Vec<Value>) {
+    let request = Request::get(uri)
+        .header("accept", accept)
+        .body(Body::empty())
+        .unwrap();
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    if status == StatusCode::OK {
+        assert_eq!(response.headers()[CONTENT_TYPE], "application/x-ndjson");
+    }
+    let bytes = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    let items = match status {
+        StatusCode::OK => String::from_utf8(bytes.to_vec())
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect(),
+        _ => vec![],
+    };
+    (status, items)
+}
+
+#[tokio::test]
+async fn exports_every_item_to_clients_asking_for_ndjson() {
+    let app = app(config());
+    for n in 0..10 {
+        create(&app, json!({"n": n})).await;
+    }
+
+    let accept = "application/json;q=0.5, application/x-ndjson";
+    let (status, items) = get_ndjson(&app, "/items?segments=3", accept).await;
+    assert_eq!(status, StatusCode::OK);
+    let mut ns: Vec<_> = items
+        .iter()
+        .map(|item| item["n"].as_i64().unwrap())
+        .collect();
+    ns.sort();
+    assert_eq!(ns, (0..10).collect::<Vec<_>>());
+
+    // Everyone else still gets pages of JSON.
+    let response = get(&app, "/items").await;
+    assert_eq!(response.body.as_array().unwrap().len(), 10);
+    assert_eq!(response.header("vary"), Some("accept"));
+
+    let accept = "application/x-ndjson";
+    for uri in ["/items?segments=1001", "/items?limit=2", "/items?n=1"] {
+        let (status, _) = get_ndjson(&app, uri, accept).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", uri);
+    }
+}
+
+#[tokio::test]
+async fn streaming_deployments_only_export() {
+    let (app, store) = spied_app(Config {
+        response_streaming: true,
+        ..config()
+    });
+    let item = serde_json::from_value(json!({"itemId": "a", "n": 1})).unwrap();
+    store.inner.put(item, None).await.unwrap();
+
+    let (status, items) = get_ndjson(&app, "/items", "*/*").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(items, [json!({"itemId": "a", "n": 1})]);
+    let (status, _) = get_ndjson(&app, "/items", "application/json").await;
+    assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
+
+    let response = send(&app, Method::POST, "/items", &[], Some(json!({}))).await;
+    assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+    assert_eq!(get(&app, "/items/x").await.status, StatusCode::NOT_FOUND);
+    assert_eq!(
+        get(&app, "/transactions").await.status,
+        StatusCode::NOT_FOUND
+    );
+}
+
+#[tokio::test]
+async fn batch_writes_report_each_outcome() {
+    let app = app(config());
+    let old = create(&app, json!({"n": 0})).await;
+
+    let body = json!({"put": [{"n": 1}, {"n": 2}], "delete": [old, "missing"]});
+    let response = send(&app, Method::POST, "/items", &[], Some(body)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let statuses = |results: &Value| -> Vec<Value> {
+        results
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|result| result["status"].clone())
+            .collect()
+    };
+    assert_eq!(statuses(&response.body["put"]), [201, 201]);
+    assert_eq!(statuses(&response.body["delete"]), [200, 200]);
+
+    let id = response.body["put"][1]["id"].as_str().unwrap();
+    assert_eq!(get(&app, &format!("/items/{}", id)).await.body["n"], 2);
+    let uri = format!("/items/{}", old);
+    assert_eq!(get(&app, &uri).await.status, StatusCode::NOT_FOUND);
+
+    // Anything else besides the lists makes the body an item.
+    let body = json!({"put": [], "n": 3});
+    let response = send(&app, Method::POST, "/items", &[], Some(body)).await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert_eq!(response.body["n"], 3);
+    let body = json!({"put": [1]});
+    let response = send(&app, Method::POST, "/items", &[], Some(body)).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn batch_writes_touch_each_item_once() {
+    let app = app(sort_key_config());
+
+    let body = json!({
+        "put": [{"itemId": "c", "sk": "1"}],
+        "delete": [{"id": "c", "sk": "1"}],
+    });
+    let response = send(&app, Method::POST, "/items", &[], Some(body)).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(get(&app, "/items/c/1").await.status, StatusCode::NOT_FOUND);
+
+    let body = json!({"put": [{"itemId": "c"}]});
+    let response = send(&app, Method::POST, "/items", &[], Some(body)).await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn transactions_apply_all_operations_or_none() {
+    let app = app(config());
+    let a = create(&app, json!({"stock": 1})).await;
+    let b = create(&app, json!({"stock": 5})).await;
+
+    let body = json!({"operations": [
+        {"op": "update", "id": a, "patch": {"$inc": {"stock": -1}}, "if_match": 1},
+        {"op": "delete", "id": b},
+        {"op": "put", "item": {"n": 1}},
+    ]});
+    let response = send(&app, Method::POST, "/transactions", &[], Some(body)).await;
+    assert_eq!(response.status, StatusCode::OK);
+    let operations = &response.body["operations"];
+    assert_eq!(operations[0]["status"], 200);
+    assert_eq!(operations[2]["status"], 201);
+    let created = operations[2]["id"].as_str().unwrap();
+    assert_eq!(get(&app, &format!("/items/{}", created)).await.body["n"], 1);
+    assert_eq!(get(&app, &format!("/items/{}", a)).await.body["stock"], 0);
+
+    // The stale version cancels the whole transaction.
+    let body = json!({"operations": [
+        {"op": "update", "id": a, "patch": {"stock": 10}},
+        {"op": "check", "id": created, "if_match": 7},
+    ]});
+    let response = send(&app, Method::POST, "/transactions", &[], Some(body)).await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    assert_eq!(response.body["operations"][0], Value::Null);
+    assert_eq!(response.body["operations"][1]["status"], 412);
+    assert_eq!(get(&app, &format!("/items/{}", a)).await.body["stock"], 0);
+}
+
+#[tokio::test]
+async fn transactions_explain_failures_like_item_routes() {
+    let app = app(sort_key_config());
+    for sk in ["0", "1"] {
+        let body = json!({"itemId": "c", "sk": sk, "status": "open"});
+        send(&app, Method::POST, "/items", &[], Some(body)).await;
+    }
+
+    let body = json!({"operations": [
+        {"op": "put", "item": {"itemId": "c", "sk": "0"}},
+        {"op": "update", "id": "c", "sk": "2", "patch": {"n": 1}},
+        {"op": "check", "id": "c", "sk": "3"},
+        {"op": "update", "id": "c", "sk": "1", "patch": [
+            {"op": "test", "path": "/status", "value": "closed"},
+        ]},
+    ]});
+    let response = send(&app, Method::POST, "/transactions", &[], Some(body)).await;
+    assert_eq!(response.status, StatusCode::CONFLICT);
+    let statuses: Vec<_> = response.body["operations"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|operation| operation["status"].clone())
+        .collect();
+    assert_eq!(statuses, [409, 404, 404, 409]);
+
+    let body = json!({"operations": [
+        {"op": "delete", "id": "c", "sk": "1"},
+        {"op": "delete", "id": "c", "sk": "1"},
+    ]});
+    let response = send(&app, Method::POST, "/transactions", &[], Some(body)).await;
+    assert!(response.status.is_client_error());
+    assert_eq!(get(&app, "/items/c/1").await.status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn replays_creates_with_the_same_idempotency_key() {
+    let app = app(config());
+    let key = [("idempotency-key", "k1")];
+
+    let first = send(&app, Method::POST, "/items", &key, Some(json!({"n": 1}))).await;
+    assert_eq!(first.status, StatusCode::CREATED);
+    assert_eq!(first.header("idempotent-replayed"), None);
+
+    let repeat = send(&app, Method::POST, "/items", &key, Some(json!({"n": 1}))).await;
+    assert_eq!(repeat.status, StatusCode::CREATED);
+    assert_eq!(repeat.header("idempotent-replayed"), Some("true"));
+    assert_eq!(repeat.header("location"), first.header("location"));
+    assert_eq!(repeat.body, first.body);
+    assert_eq!(get(&app, "/items").await.body.as_array().unwrap().len(), 1);
+
+    let other = send(&app, Method::POST, "/items", &key, Some(json!({"n": 2}))).await;
+    assert_eq!(other.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+#[tokio::test]
+async fn filters_collections() {
+    let app = app(config());
+    for (status, n) in [("open", 1), ("open", 5), ("closed", 9)] {
+        create(&app, json!({"status": status, "n": n})).await;
+    }
+
+    let response = get(&app, "/items?status=open&n[ge]=2").await;
+    assert_eq!(response.status, StatusCode::OK);
+    let items = response.body.as_array().unwrap();
+    assert_eq!(items.len(), 1);
+    assert_eq!(items[0]["n"], 5);
+
+    // Filters apply to the items a page reads, so pages may come up short.
+    let pages = get_pages(&app, "/items?status=closed&limit=1").await;
+    assert_eq!(pages.concat().len(), 1);
+    assert_eq!(pages.len(), 4);
+
+    let response = get(&app, "/items?n[near]=1").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn projects_fields_of_items_and_collections() {
+    let app = app(config());
+    let body = json!({"name": "x", "meta": {"color": "red", "size": 2}, "n": 1});
+    send(&app, Method::PUT, "/items/x", &[], Some(body)).await;
+
+    let response = get(&app, "/items/x?fields=meta.color,n").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(
+        response.body,
+        json!({"itemId": "x", "meta": {"color": "red"}, "n": 1})
+    );
+    assert_eq!(response.header("etag"), Some("\"1\""));
+
+    let response = get(&app, "/items/x?fields=_version").await;
+    assert_eq!(response.body, json!({"itemId": "x", "_version": 1}));
+
+    let response = get(&app, "/items?fields=name").await;
+    assert_eq!(response.body, json!([{"itemId": "x", "name": "x"}]));
+}
+
+#[tokio::test]
+async fn projections_still_answer_conditional_gets() {
+    let app = app(config());
+    send(
+        &app,
+        Method::PUT,
+        "/items/x",
+        &[],
+        Some(json!({"name": "x"})),
+    )
+    .await;
+
+    let current = [("if-none-match", "\"1\"")];
+    let response = send(&app, Method::GET, "/items/x?fields=name", &current, None).await;
+    assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+    assert_eq!(response.header("etag"), Some("\"1\""));
+
+    let stale = [("if-none-match", "\"0\"")];
+    let response = send(&app, Method::GET, "/items/x?fields=name", &stale, None).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body, json!({"itemId": "x", "name": "x"}));
+}
+
+#[tokio::test]
+async fn locations_encode_client_chosen_keys() {
+    let app = app(sort_key_config());
+
+    let body = json!({"itemId": "cust 1", "sk": "ORDER#2024-02"});
+    let response = send(&app, Method::POST, "/items", &[], Some(body)).await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    let location = response.header("location").unwrap();
+    assert_eq!(location, "/items/cust%201/ORDER%232024-02");
+    let response = get(&app, location).await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body["sk"], "ORDER#2024-02");
+
+    let uri = "/items/cust%201/a%2Fb";
+    let response = send(&app, Method::PUT, uri, &[], Some(json!({}))).await;
+    assert_eq!(response.status, StatusCode::CREATED);
+    assert_eq!(response.header("location"), Some(uri));
+    assert_eq!(response.body["sk"], "a/b");
+}
+
+#[tokio::test]
+async fn queries_the_items_of_a_partition() {
+    let app = app(sort_key_config());
+    for sk in ["2024-03", "2023-12", "2024-01", "2024-02"] {
+        let body = json!({"itemId": "cust1", "sk": sk});
+        send(&app, Method::POST, "/items", &[], Some(body.clone())).await;
+        let response = send(&app, Method::POST, "/items", &[], Some(body)).await;
+        assert_eq!(response.status, StatusCode::CONFLICT);
+    }
+    send(
+        &app,
+        Method::POST,
+        "/items",
+        &[],
+        Some(json!({"itemId": "other", "sk": "2024-01"})),
+    )
+    .await;
+
+    let sort_keys = |pages: Vec<Vec<Value>>| -> Vec<Value> {
+        pages
+            .concat()
+            .iter()
+            .map(|item| item["sk"].clone())
+            .collect()
+    };
+    let pages = get_pages(&app, "/items/cust1?begins_with=2024-&order=desc&limit=2").await;
+    assert_eq!(sort_keys(pages), ["2024-03", "2024-02", "2024-01"]);
+    let pages = get_pages(&app, "/items/cust1?from=2024-01&to=2024-02").await;
+    assert_eq!(sort_keys(pages), ["2024-01", "2024-02"]);
+
+    let response = get(&app, "/items/cust1/2024-01").await;
+    assert_eq!(response.status, StatusCode::OK);
+    let response = get(&app, "/items/cust1?begins_with=a&from=b").await;
+    assert_eq!(response.status, StatusCode::BAD_REQUEST);
+
+    // Keys cannot be changed by patching.
+    let patch = json!({"sk": "2030"});
+    let response = send(
+        &app,
+        Method::PATCH,
+        "/items/cust1/2024-01",
+        &[],
+        Some(patch),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+
+    let response = send(
+        &app,
+        Method::POST,
+        "/items",
+        &[],
+        Some(json!({"itemId": "x"})),
+    )
+    .await;
+    assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+}
+
+fn index_config() -> Config {
+    Config {
+        indexes: vec![
+            Index {
+                name: "byStatus".into(),
+                keys: KeySchema {
+                    pk: "status".into(),
+                    sk: Some("due".into()),
+                },
+            },
+            Index {
+                name: "byOwner".into(),
+                keys: KeySchema {
+                    pk: "owner".into(),
+                    sk: None,
+                },
+            },
+        ],
+        ..config()
+    }
+}
+
+#[tokio::test]
+async fn queries_secondary_indexes() {
+    let app = app(index_config());
+    for (status, due, owner) in [
+        ("open", "2024-03", "ann"),
+        ("open", "2024-01", "bob"),
+        ("closed", "2024-02", "ann"),
+        ("open", "2024-02", "ann"),
+    ] {
+        create(&app, json!({"status": status, "due": due, "owner": owner})).await;
+    }
+    // Items without the index keys are not in the index.
+    create(&app, json!({"status": "open"})).await;
+
+    let dues = |pages: Vec<Vec<Value>>| -> Vec<Value> {
+        pages
+            .concat()
+            .iter()
+            .map(|item| item["due"].clone())
+            .collect()
+    };
+    let pages = get_pages(&app, "/items?index=byStatus&status=open&limit=2").await;
+    assert_eq!(dues(pages), ["2024-01", "2024-02", "2024-03"]);
+    let pages = get_pages(
+        &app,
+        "/items?index=byStatus&status=open&from=2024-02&order=desc",
+    )
+    .await;
+    assert_eq!(dues(pages), ["2024-03", "2024-02"]);
+    let pages = get_pages(&app, "/items?index=byStatus&status=open&owner=ann").await;
+    assert_eq!(dues(pages), ["2024-02", "2024-03"]);
+    let pages = get_pages(&app, "/items?index=byOwner&owner=ann&limit=1").await;
+    assert_eq!(pages.concat().len(), 3);
+}
+
+#[tokio::test]
+async fn rejects_invalid_index_queries() {
+    let app = app(index_config());
+
+    for uri in [
+        "/items?index=missing&status=open",
+        "/items?index=byStatus",
+        "/items?index=byOwner&owner=ann&begins_with=2024",
+        "/items?index=byStatus&status=open&segments=2",
+        "/items?index=byStatus&status=open&order=sideways",
+    ] {
+        assert_eq!(
+            get(&app, uri).await.status,
+            StatusCode::BAD_REQUEST,
+            "{}",
+            uri
+        );
+    }
+}
+
+#[tokio::test]
+async fn scans_filter_on_sort_key_parameter_names() {
+    let app = app(index_config());
+    create(&app, json!({"order": "open", "from": "a"})).await;
+    create(&app, json!({"order": "closed", "from": "b"})).await;
+
+    let response = get(&app, "/items?order=open").await;
+    assert_eq!(response.status, StatusCode::OK);
+    assert_eq!(response.body.as_array().unwrap().len(), 1);
+    assert_eq!(response.body[0]["from"], "a");
+    let response = get(&app, "/items?from=b").await;
+    assert_eq!(response.body[0]["order"], "closed");
+}
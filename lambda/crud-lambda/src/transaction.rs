@@ -0,0 +1,92 @@
+use serde::Deserialize;
+
+use crate::{
+    conditional::IfMatch,
+    patch::PatchBody,
+    store::{Item, Key},
+};
+
+/// Most operations DynamoDB accepts in one transaction.
+pub const MAX_OPERATIONS: usize = 100;
+
+/// One operation of a `POST /transactions` body. Bodies follow the item
+/// routes: `put` creates an item like `POST /items`, `update` takes a PATCH
+/// body, and `if_match` holds the version an `If-Match` header would. `id`
+/// and, on tables with a sort key, `sk` name the item.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum TransactionOperation {
+    Put {
+        item: Item,
+    },
+    Update {
+        id: String,
+        sk: Option<String>,
+        patch: PatchBody,
+        #[serde(default)]
+        upsert: bool,
+        if_match: Option<u64>,
+    },
+    Delete {
+        id: String,
+        sk: Option<String>,
+        if_match: Option<u64>,
+    },
+    /// Writes nothing, but requires the item to exist at the given version.
+    Check {
+        id: String,
+        sk: Option<String>,
+        if_match: Option<u64>,
+    },
+}
+
+/// What an operation does, kept to explain its failure once it is compiled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OperationKind {
+    Put,
+    /// An update, and whether its patch reads the item first, which makes it
+    /// conditional on the item not changing meanwhile.
+    Update {
+        reads_current: bool,
+    },
+    Delete,
+    Check,
+}
+
+impl TransactionOperation {
+    pub fn kind(&self) -> OperationKind {
+        match self {
+            TransactionOperation::Put { .. } => OperationKind::Put,
+            TransactionOperation::Update { patch, .. } => OperationKind::Update {
+                reads_current: patch.reads_current(),
+            },
+            TransactionOperation::Delete { .. } => OperationKind::Delete,
+            TransactionOperation::Check { .. } => OperationKind::Check,
+        }
+    }
+
+    pub fn if_match(&self) -> Option<IfMatch> {
+        match self {
+            TransactionOperation::Put { .. } => None,
+            TransactionOperation::Update { if_match, .. }
+            | TransactionOperation::Delete { if_match, .. }
+            | TransactionOperation::Check { if_match, .. } => if_match.map(IfMatch::Version),
+        }
+    }
+
+    /// The key of the item the operation is for; `None` for a `put`, whose
+    /// key is in its item.
+    pub fn key(&self) -> Option<Key> {
+        match self {
+            TransactionOperation::Put { .. } => None,
+            TransactionOperation::Update { id, sk, .. }
+            | TransactionOperation::Delete { id, sk, .. }
+            | TransactionOperation::Check { id, sk, .. } => Some(Key::new(id.as_str(), sk.clone())),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TransactionRequest {
+    pub operations: Vec<TransactionOperation>,
+}
@@ -1,7 +1,7 @@
 import * as cdk from 'aws-cdk-lib';
 import { LambdaRestApi } from 'aws-cdk-lib/aws-apigateway';
 import { AttributeType } from 'aws-cdk-lib/aws-dynamodb';
-import { Architecture, Runtime } from 'aws-cdk-lib/aws-lambda';
+import { Architecture, FunctionUrlAuthType, InvokeMode, Runtime } from 'aws-cdk-lib/aws-lambda';
 import { Construct } from 'constructs';
 
 export class ApiCorsLambdaCrudDynamodbStack extends cdk.Stack {
@@ -17,6 +17,15 @@ export class ApiCorsLambdaCrudDynamodbStack extends cdk.Stack {
       removalPolicy: cdk.RemovalPolicy.DESTROY
     });
 
+    const idempotencyTable = new cdk.aws_dynamodb.Table(this, 'idempotency', {
+      partitionKey: {
+        name: 'key',
+        type: AttributeType.STRING
+      },
+      timeToLiveAttribute: 'expires_at',
+      removalPolicy: cdk.RemovalPolicy.DESTROY
+    });
+
     const itemsLambda = new cdk.aws_lambda.Function(this, 'itemsLambda', {
       runtime: Runtime.PROVIDED_AL2023,
       architecture: Architecture.ARM_64,
@@ -24,15 +33,46 @@ export class ApiCorsLambdaCrudDynamodbStack extends cdk.Stack {
       handler: 'not.required',
       environment: {
         PK: 'itemId',
-        TABLE_NAME: dynamoTable.tableName
+        TABLE_NAME: dynamoTable.tableName,
+        IDEMPOTENCY_TABLE_NAME: idempotencyTable.tableName,
+        LEGACY_ROUTES: 'true'
       }
     });
 
     dynamoTable.grantReadWriteData(itemsLambda);
+    idempotencyTable.grantReadWriteData(itemsLambda);
 
     new LambdaRestApi(this, 'itemsApi', {
       handler: itemsLambda
     });
 
+    // API Gateway buffers responses, which caps them at 6 MB and 29 seconds,
+    // so exports stream from a function URL of their own instead. It dumps
+    // the whole table, so callers must sign their requests, and it only ever
+    // runs a couple of exports at once.
+    const exportLambda = new cdk.aws_lambda.Function(this, 'exportLambda', {
+      runtime: Runtime.PROVIDED_AL2023,
+      architecture: Architecture.ARM_64,
+      code: cdk.aws_lambda.Code.fromAsset('lambda/target/lambda/crud-lambda'),
+      handler: 'not.required',
+      timeout: cdk.Duration.minutes(15),
+      reservedConcurrentExecutions: 2,
+      environment: {
+        PK: 'itemId',
+        TABLE_NAME: dynamoTable.tableName,
+        RESPONSE_STREAMING: 'true'
+      }
+    });
+
+    dynamoTable.grantReadData(exportLambda);
+
+    const exportUrl = exportLambda.addFunctionUrl({
+      authType: FunctionUrlAuthType.AWS_IAM,
+      invokeMode: InvokeMode.RESPONSE_STREAM
+    });
+
+    new cdk.CfnOutput(this, 'exportUrl', {
+      value: `${exportUrl.url}items`
+    });
   }
 }